@@ -0,0 +1,43 @@
+// Debug visualizations for the debug camera, see debug_render.rs
+#import bevy_pbr::{
+	forward_io::VertexOutput,
+	mesh_view_bindings::view,
+}
+
+struct DebugViewSettings {
+	mode: u32, // 0: flat normals, 1: uv checker, 2: depth
+	checker_scale: f32,
+	depth_range: f32,
+}
+@group(#{MATERIAL_BIND_GROUP}) @binding(0) var<uniform> settings: DebugViewSettings;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+	let to_cam = view.world_position - in.world_position.xyz;
+	
+	if settings.mode == 0u {
+		// Face normal from screen space derivatives, flipped to face the camera
+		var n = normalize(cross(dpdx(in.world_position.xyz), dpdy(in.world_position.xyz)));
+		if dot(n, to_cam) < 0.0 {
+			n = -n;
+		}
+		return vec4(n * 0.5 + 0.5, 1.0);
+	}
+	else if settings.mode == 1u {
+#ifdef VERTEX_UVS_A
+		let uv = in.uv * settings.checker_scale;
+		let checker = abs(floor(uv.x) + floor(uv.y)) % 2.0;
+		// Tint by uv, so stretching and orientation are visible too
+		let tint = vec3(fract(in.uv), 0.5);
+		return vec4(mix(tint * 0.4, tint, checker), 1.0);
+#else
+		// No uvs
+		return vec4(1.0, 0.0, 1.0, 1.0);
+#endif
+	}
+	else {
+		// Log scale distance, white close to black at depth_range
+		let d = log2(1.0 + length(to_cam)) / log2(1.0 + settings.depth_range);
+		return vec4(vec3(1.0 - clamp(d, 0.0, 1.0)), 1.0);
+	}
+}
@@ -0,0 +1,34 @@
+// Instanced particles, see particle_render.rs
+#import bevy_pbr::view_transformations::position_world_to_clip
+
+struct Vertex {
+	@location(0) position: vec3<f32>,
+	@location(1) normal: vec3<f32>,
+	// Per instance, world space
+	@location(3) i_pos_scale: vec4<f32>,
+	@location(4) i_color: vec4<f32>,
+};
+
+struct VertexOutput {
+	@builtin(position) clip_position: vec4<f32>,
+	@location(0) normal: vec3<f32>,
+	@location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+	let world_pos = vertex.position * vertex.i_pos_scale.w + vertex.i_pos_scale.xyz;
+	
+	var out: VertexOutput;
+	out.clip_position = position_world_to_clip(world_pos);
+	out.normal = vertex.normal;
+	out.color = vertex.i_color;
+	return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+	// Cheap fixed lighting, instanced particles don't go through the pbr lighting
+	let light = 0.6 + 0.4 * max(dot(normalize(in.normal), normalize(vec3(-0.3, 1.0, 0.5))), 0.0);
+	return vec4(in.color.rgb * light, in.color.a);
+}
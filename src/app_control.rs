@@ -6,6 +6,8 @@ use egui::{Ui, RichText, Color32};
 use crate::phases::Phase;
 use crate::serialization::*;
 use crate::settings_file;
+use crate::cursor::{ self, CursorManager };
+use bevy_egui::input::EguiWantsInput;
 
 pub struct AppControlPlugin;
 impl Plugin for AppControlPlugin {
@@ -65,11 +67,16 @@ impl WindowSettings {
 
 fn window_control(
 	keyboard: Res<ButtonInput<KeyCode>>,
-	window: Single<&mut Window>,
+	cursor: Res<CursorManager>,
+	egui_wants: Res<EguiWantsInput>,
+	// Fullscreen and vsync are for the main window, other windows keep their own
+	window: Single<&mut Window, With<PrimaryWindow>>,
 	mut settings: ResMut<WindowSettings>
 ) {
-	if keyboard.just_pressed(KeyCode::F11) ||
-		(keyboard.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) && keyboard.just_pressed(KeyCode::Enter)) {
+	let hotkeys = !cursor::keyboard_captured_by_ui(&cursor, &egui_wants);
+	
+	if hotkeys && (keyboard.just_pressed(KeyCode::F11) ||
+		(keyboard.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]) && keyboard.just_pressed(KeyCode::Enter))) {
 		
 		settings.fullscreen = !settings.fullscreen;
 	}
@@ -80,11 +87,16 @@ fn window_control(
 pub fn save_load_controls(
 	world: &mut World,
 	params: &mut SystemState<(
-		Res<ButtonInput<KeyCode>>
+		Res<ButtonInput<KeyCode>>,
+		Res<CursorManager>,
+		Res<EguiWantsInput>,
 	)>
 ) {
 	let (do_load, do_save) = {
-		let keyboard = params.get(world);
+		let (keyboard, cursor, egui_wants) = params.get(world);
+		if cursor::keyboard_captured_by_ui(&cursor, &egui_wants) {
+			return;
+		}
 		(keyboard.just_pressed(KeyCode::Semicolon), keyboard.just_pressed(KeyCode::Quote))
 	};
 	if do_load {
@@ -100,6 +112,7 @@ fn main_ui(
 	sys: &mut SystemState<(
 		Res<Time>,
 		ResMut<WindowSettings>,
+		ResMut<crate::overlays::ReferenceOverlays>,
 		MessageWriter<AppExit>,
 		Local<crate::egui_histogram::Frametimes>,
 		Commands,
@@ -117,6 +130,7 @@ fn main_ui(
 		let (
 			time,
 			mut window_settings,
+			mut overlays,
 			mut exit,
 			mut frametimes,
 			commands,
@@ -142,6 +156,16 @@ fn main_ui(
 		
 		frametimes.gui(ui, time);
 		
+		let mut ov = overlays.clone();
+		ui.horizontal(|ui| {
+			ui.checkbox(&mut ov.grid, "Grid");
+			ui.checkbox(&mut ov.axes, "Axes");
+			ui.checkbox(&mut ov.aabbs, "Bounding Boxes");
+		});
+		if (ov.grid, ov.axes, ov.aabbs) != (overlays.grid, overlays.axes, overlays.aabbs) {
+			*overlays = ov;
+		}
+		
 		ui.add_space(6.0);
 		
 		ui.horizontal(|ui| {
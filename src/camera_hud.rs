@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+use bevy_egui::*;
+use egui::{ Color32, RichText };
+use bevy_egui::input::EguiWantsInput;
+use crate::phases::Phase;
+use crate::serialization::*;
+use crate::cursor::{ self, CursorManager };
+use crate::flycam::{ Flycam, ControlledFlycam };
+
+pub struct CameraHudPlugin;
+impl Plugin for CameraHudPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(CameraHud::default())
+			.add_systems(Update, toggle_hud.in_set(Phase::Start))
+			// See the final values of this frame
+			.add_systems(Update, detect_changes.after(Phase::CameraUpdate))
+			.add_systems(EguiPrimaryContextPass, hud_ui);
+	}
+}
+
+const TOGGLE_HUD_KEY : KeyCode = KeyCode::F3;
+
+const TOAST_FADE_IN : f32 = 0.1;
+const TOAST_HOLD : f32 = 1.0;
+const TOAST_FADE_OUT : f32 = 0.5;
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct CameraHud {
+	pub visible: bool,
+	// Briefly show speed and fov changes, even with the HUD hidden
+	pub toasts: bool,
+	
+	toast: String,
+	toast_age: f32,
+}
+impl Default for CameraHud {
+	fn default() -> Self {
+		Self {
+			visible: false,
+			toasts: true,
+			toast: String::new(),
+			toast_age: f32::INFINITY,
+		}
+	}
+}
+serializer!(CameraHud, visible, toasts);
+serializer_world!(CameraHud, Res<CameraHud>);
+
+impl CameraHud {
+	fn show_toast(&mut self, text: String) {
+		// Keep fully visible if already showing, ex. while scrolling through speeds
+		self.toast_age = if self.toast_alpha() > 0.0 { self.toast_age.min(TOAST_FADE_IN) } else { 0.0 };
+		self.toast = text;
+	}
+	fn toast_alpha(&self) -> f32 {
+		let t = self.toast_age;
+		if t < TOAST_FADE_IN { t / TOAST_FADE_IN }
+		else if t < TOAST_FADE_IN + TOAST_HOLD { 1.0 }
+		else { (1.0 - (t - TOAST_FADE_IN - TOAST_HOLD) / TOAST_FADE_OUT).max(0.0) }
+	}
+}
+
+fn toggle_hud(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		mut hud: ResMut<CameraHud>) {
+	if keyboard.just_pressed(TOGGLE_HUD_KEY) && !cursor::keyboard_captured_by_ui(&cursor, &egui_wants) {
+		hud.visible = !hud.visible;
+	}
+}
+
+fn fov_text(proj: &Projection) -> String {
+	match proj {
+		Projection::Perspective(persp) => format!("FOV: {:.1}°", persp.fov.to_degrees()),
+		Projection::Orthographic(ortho) => format!("Ortho height: {:.2} m", ortho.area.height()),
+		_ => String::new(),
+	}
+}
+
+fn detect_changes(
+		time: Res<Time<Real>>,
+		control: Res<ControlledFlycam>,
+		cameras: Query<&Flycam>,
+		mut last: Local<Option<(Entity, f32, f32, f32)>>, // camera, base_speed, vfov_target, ortho_scale_target
+		mut hud: ResMut<CameraHud>) {
+	// Real time, so toasts still fade while paused
+	hud.toast_age += time.delta_secs();
+	
+	let Some((cam_e, flycam)) = control.current().and_then(|e| Some((e, cameras.get(e).ok()?))) else { return };
+	let current = (cam_e, flycam.base_speed, flycam.vfov_target, flycam.ortho_scale_target);
+	
+	// Switching cameras is not a change
+	if let Some((prev_e, base_speed, vfov, ortho)) = *last && prev_e == cam_e && hud.toasts {
+		if flycam.base_speed != base_speed {
+			hud.show_toast(format!("Speed: {:.2} m/s", flycam.base_speed));
+		}
+		else if flycam.vfov_target != vfov {
+			hud.show_toast(format!("FOV: {:.1}°", flycam.vfov_target.to_degrees()));
+		}
+		else if flycam.ortho_scale_target != ortho {
+			hud.show_toast(format!("Ortho height: {:.2} m", flycam.ortho_scale_target));
+		}
+	}
+	*last = Some(current);
+}
+
+fn hud_ui(
+		mut contexts: EguiContexts,
+		hud: Res<CameraHud>,
+		control: Res<ControlledFlycam>,
+		cameras: Query<(&Flycam, &Transform, &Projection, Option<&Name>)>) -> Result {
+	let alpha = hud.toast_alpha();
+	if !hud.visible && alpha <= 0.0 {
+		return Ok(());
+	}
+	
+	let ctx = contexts.ctx_mut()?;
+	
+	if alpha > 0.0 {
+		egui::Area::new(egui::Id::new("camera_hud_toast"))
+			.anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+			.interactable(false)
+			.show(ctx, |ui| {
+				ui.set_opacity(alpha);
+				egui::Frame::popup(ui.style()).show(ui, |ui| {
+					ui.label(RichText::new(&hud.toast).size(20.0).strong());
+				});
+			});
+	}
+	
+	if !hud.visible {
+		return Ok(());
+	}
+	let Some(cam_e) = control.current() else { return Ok(()) };
+	let Ok((flycam, transf, proj, name)) = cameras.get(cam_e) else { return Ok(()) };
+	
+	egui::Area::new(egui::Id::new("camera_hud"))
+		.anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(8.0, -8.0))
+		.interactable(false)
+		.show(ctx, |ui| {
+			egui::Frame::popup(ui.style()).show(ui, |ui| {
+				let name = name.map_or("Camera".to_string(), |n| n.to_string());
+				ui.label(RichText::new(name).strong().color(Color32::LIGHT_BLUE));
+				
+				ui.label(format!("Speed: {:.2} m/s (base {:.2})", flycam.speed, flycam.base_speed));
+				ui.label(fov_text(proj));
+				
+				let p = transf.translation;
+				ui.label(format!("Pos: {:8.2} {:8.2} {:8.2}", p.x, p.y, p.z));
+				let (yaw, pitch, roll) = transf.rotation.to_euler(EulerRot::YXZ);
+				ui.label(format!("Yaw/Pitch/Roll: {:6.1}° {:6.1}° {:6.1}°", yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees()));
+				
+				let mode = if flycam.walk { "Walk" } else if flycam.six_dof { "6-DOF" } else if flycam.move_planar { "Planar" } else { "Free" };
+				ui.label(format!("Mode: {mode}"));
+				
+				ui.label(RichText::new("[F3] toggle HUD").small().weak());
+			});
+		});
+	
+	Ok(())
+}
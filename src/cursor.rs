@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use bevy::platform::collections::HashMap;
+use bevy::window::{ CursorGrabMode, CursorIcon, CursorOptions, SystemCursorIcon };
+use bevy_egui::{ EguiContext, PrimaryEguiContext, input::EguiWantsInput };
+use crate::phases::Phase;
+
+pub struct CursorPlugin;
+impl Plugin for CursorPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(CursorManager::default())
+			.add_systems(Update, update_mouselook.in_set(Phase::Start))
+			// After everything had a chance to submit requests
+			.add_systems(Update, apply_cursor.after(Phase::CameraUpdate).after(Phase::Gameplay));
+	}
+}
+
+pub const MOUSELOOK_BTN : MouseButton = MouseButton::Middle;
+
+// Request priorities, higher wins
+pub const PRIORITY_HOVER : i32 = 10; // Ex. gizmo handles changing the cursor icon
+pub const PRIORITY_DRAG : i32 = 20; // Ex. dragging a gizmo handle
+pub const PRIORITY_MOUSELOOK : i32 = 30;
+
+// Systems submit requests every frame they want to control the cursor
+// Each property is taken from the highest priority request that sets it, unset properties fall back to the defaults
+#[derive(Clone, Default, Debug)]
+pub struct CursorRequest {
+	pub priority: i32,
+	pub grab_mode: Option<CursorGrabMode>,
+	pub visible: Option<bool>,
+	pub icon: Option<CursorIcon>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct CursorState {
+	grab_mode: CursorGrabMode,
+	visible: bool,
+	icon: Option<CursorIcon>, // None removes CursorIcon from the window (default icon)
+}
+impl Default for CursorState {
+	fn default() -> Self {
+		Self { grab_mode: CursorGrabMode::None, visible: true, icon: None }
+	}
+}
+
+// Owns the window cursors, so the flycam, gizmos and UI can share them
+// Requests apply to the focused window, all other windows get the default cursor
+// Also tracks mouselook (MOUSELOOK_BTN held or cursor hidden via F2), which other systems can query
+#[derive(Resource, Default)]
+pub struct CursorManager {
+	requests: Vec<CursorRequest>,
+	applied: HashMap<Entity, CursorState>,
+	// Window with keyboard focus, None if the app is in the background
+	focused: Option<Entity>,
+	
+	mouselook: bool,
+	mouselook_started: bool,
+	button_mouselook: bool, // MOUSELOOK_BTN pressed over the viewport and still held
+	cursor_hidden: bool, // FPS style mouselook toggled via F2
+}
+impl CursorManager {
+	pub fn request(&mut self, request: CursorRequest) {
+		self.requests.push(request);
+	}
+	
+	pub fn is_mouselook(&self) -> bool { self.mouselook }
+	pub fn focused_window(&self) -> Option<Entity> { self.focused }
+	// First frame of mouselook
+	pub fn mouselook_started(&self) -> bool { self.mouselook_started }
+	
+	fn resolve(&self) -> CursorState {
+		let mut state = CursorState::default();
+		
+		let mut sorted: Vec<&CursorRequest> = self.requests.iter().collect();
+		// stable sort, so equal priorities resolve to whoever requested first
+		sorted.sort_by_key(|r| std::cmp::Reverse(r.priority));
+		
+		if let Some(grab) = sorted.iter().find_map(|r| r.grab_mode) { state.grab_mode = grab; }
+		if let Some(visible) = sorted.iter().find_map(|r| r.visible) { state.visible = visible; }
+		state.icon = sorted.iter().find_map(|r| r.icon.clone());
+		state
+	}
+}
+
+// Keyboard input belongs to egui (ex. typing into the inspector), unless we are mouselooking in the viewport
+// Camera controls and hotkeys should ignore the keyboard while this is true
+pub fn keyboard_captured_by_ui(cursor: &CursorManager, egui_wants: &EguiWantsInput) -> bool {
+	egui_wants.wants_any_keyboard_input() && !cursor.is_mouselook()
+}
+// Same for the pointer, ex. scrolling an egui window should not also change the camera speed
+pub fn pointer_captured_by_ui(cursor: &CursorManager, egui_wants: &EguiWantsInput) -> bool {
+	egui_wants.wants_any_pointer_input() && !cursor.is_mouselook()
+}
+
+fn update_mouselook(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		mouse: Res<ButtonInput<MouseButton>>,
+		windows: Query<(Entity, &Window)>,
+		egui_wants: Res<EguiWantsInput>,
+		mut egui_contexts: Query<&mut EguiContext, With<PrimaryEguiContext>>,
+		mut cursor: ResMut<CursorManager>) {
+	
+	let focused = windows.iter().find(|(_, w)| w.focused).map(|(e, _)| e);
+	// Switching windows ends mouselook like alt-tab, it would stay locked to the old window otherwise
+	let switched = focused.is_some() && cursor.focused.is_some() && focused != cursor.focused;
+	cursor.focused = focused;
+	
+	// Handle alt-tab gracefully by fulling resetting everything
+	if focused.is_none() || switched {
+		//println!("No Window Focus!");
+		cursor.cursor_hidden = false;
+		cursor.button_mouselook = false;
+		cursor.mouselook = false;
+		cursor.mouselook_started = false;
+		return;
+	}
+	
+	// Toggle mouse cursor visible via F2 (invisible cursor = FPS style mouselook)
+	if keyboard.just_pressed(KeyCode::F2) && !keyboard_captured_by_ui(&cursor, &egui_wants) {
+		//println!("Toggle Cursor Visible");
+		cursor.cursor_hidden = !cursor.cursor_hidden;
+	}
+	
+	// Only start mouselook when pressing over the 3D viewport, not over egui windows
+	// but once started keep going even if the cursor ends up over egui
+	if mouse.just_pressed(MOUSELOOK_BTN) && !egui_wants.wants_any_pointer_input() {
+		cursor.button_mouselook = true;
+	}
+	if !mouse.pressed(MOUSELOOK_BTN) {
+		cursor.button_mouselook = false;
+	}
+	
+	// Mouselooking using held MOUSELOOK_BTN or when cursor invisible
+	let was_mouselook = cursor.mouselook;
+	cursor.mouselook = cursor.cursor_hidden || cursor.button_mouselook;
+	cursor.mouselook_started = cursor.mouselook && !was_mouselook;
+	
+	// Hand keyboard over to the camera, otherwise a focused text field would keep eating WASD after mouselook ends
+	if cursor.mouselook_started {
+		for mut ctx in &mut egui_contexts {
+			ctx.get_mut().memory_mut(|mem| mem.stop_text_input());
+		}
+	}
+	
+	if cursor.mouselook {
+		// Lock mouse cursor, which means freeze in place, and change icon to a "We are mouselooking/dragging" style icon
+		// This feels more professional and clean than making it invisible or only constraining it to the window
+		let hidden = cursor.cursor_hidden;
+		cursor.request(CursorRequest {
+			priority: PRIORITY_MOUSELOOK,
+			grab_mode: Some(CursorGrabMode::Locked),
+			visible: Some(!hidden),
+			icon: Some(SystemCursorIcon::AllScroll.into()),
+		});
+	}
+}
+
+// Make sure to add and remove CursorIcon from Window when needed
+// both writing SystemCursorIcon::Default to it every frame and writing SystemCursorIcon::Default to it only to reset
+// both cause flickering when resizing window (bug in bevy?)
+// so only touch the window when the resolved state actually changes
+fn apply_cursor(
+		windows: Query<(Entity, &mut CursorOptions), With<Window>>,
+		mut cursor: ResMut<CursorManager>,
+		mut commands: Commands) {
+	
+	let resolved = cursor.resolve();
+	cursor.requests.clear();
+	
+	// Forget closed windows
+	cursor.applied.retain(|window_e, _| windows.contains(*window_e));
+	
+	for (window_e, mut cursor_options) in windows {
+		let state = if cursor.focused == Some(window_e) { resolved.clone() } else { CursorState::default() };
+		
+		if cursor.applied.get(&window_e) == Some(&state) {
+			continue;
+		}
+		
+		cursor_options.grab_mode = state.grab_mode;
+		cursor_options.visible = state.visible;
+		match &state.icon {
+			Some(icon) => { commands.entity(window_e).insert(icon.clone()); }
+			None => { commands.entity(window_e).remove::<CursorIcon>(); }
+		}
+		
+		cursor.applied.insert(window_e, state);
+	}
+}
@@ -1,7 +1,14 @@
 use bevy::prelude::*;
+use bevy::camera::{ CameraProjection, Viewport, primitives::Frustum, visibility::VisibilitySystems };
+use bevy::window::PrimaryWindow;
+use bevy::light::CascadeShadowConfig;
 use crate::flycam::Flycam;
 use crate::phases::Phase;
 use crate::serialization::*;
+use crate::cursor::{ self, CursorManager };
+use crate::debug_render::DebugRenderMode;
+use bevy_egui::*;
+use bevy_egui::input::EguiWantsInput;
 
 pub struct DebugCameraPlugin;
 impl Plugin for DebugCameraPlugin {
@@ -10,42 +17,444 @@ impl Plugin for DebugCameraPlugin {
 			.insert_resource(DebugCameraState::default())
 			.add_systems(Update, update
 			.before(Phase::CameraUpdate)
-		);
+		)
+			.add_systems(Update, draw_main_camera_frustum.after(Phase::CameraUpdate))
+			.add_systems(EguiPrimaryContextPass, debug_camera_ui)
+			// Replace the frusta bevy just computed, before they are used for culling
+			.add_systems(PostUpdate, apply_frozen_frustum
+				.after(VisibilitySystems::UpdateFrusta)
+				.before(VisibilitySystems::CheckVisibility));
 	}
 }
 
 #[derive(Component)]
 pub struct MainCamera;
 
+// Any number of debug cameras, one of them is viewed at a time
+// Cycled in spawn (Entity) order, named via Name
 #[derive(Component)]
 pub struct DebugCamera;
 
-#[derive(Resource, Default, Reflect)]
+pub fn debug_camera(name: String, transf: Transform) -> impl Bundle {
+	(
+		DebugCamera,
+		Flycam::new(transf),
+		Camera { is_active: false, ..default() },
+		bevy::render::view::Hdr,
+		bevy::core_pipeline::tonemapping::Tonemapping::TonyMcMapface,
+		bevy::post_process::bloom::Bloom::NATURAL,
+		Name::new(name),
+	)
+}
+
+#[derive(Resource, Reflect)]
 #[reflect(Resource)]
 pub struct DebugCameraState {
 	viewing_debug_cam : bool,
+	// Entering debug view moves the debug camera to the main camera, otherwise it stays where it was left
+	pub copy_main_on_enter : bool,
+	// Viewed debug camera, falls back to the first one
+	pub active_cam : Option<Entity>,
+	
+	// Gizmos showing the main camera while viewing from the debug camera
+	pub show_frustum : bool,
+	// Cap for drawing the far plane, the actual far plane is usually much too far away to be useful
+	pub frustum_max_dist : f32,
+	// Cascade splits of shadowed directional lights along the main camera frustum
+	pub show_cascades : bool,
+	
+	// Keep rendering the main camera into a corner viewport while viewing from the debug camera
+	pub pip : bool,
+	pub pip_size : f32, // fraction of the window size
+	pub pip_corner : PipCorner,
+	
+	// Debug visualization applied while viewing from the debug camera
+	pub render_mode : DebugRenderMode,
+	
+	// Frozen culling: cameras keep culling with the main camera frustum from when it was frozen
+	// so flying the debug camera around shows exactly what the main camera culled
+	#[reflect(ignore)]
+	frozen_frustum : Option<(Frustum, [Vec3; 8])>, // frustum and world space corners for drawing
+}
+impl Default for DebugCameraState {
+	fn default() -> Self {
+		Self {
+			viewing_debug_cam: false,
+			copy_main_on_enter: true,
+			active_cam: None,
+			show_frustum: true,
+			frustum_max_dist: 50.0,
+			show_cascades: false,
+			pip: false,
+			pip_size: 0.3,
+			pip_corner: PipCorner::BottomRight,
+			render_mode: DebugRenderMode::Normal,
+			frozen_frustum: None,
+		}
+	}
+}
+serializer!(DebugCameraState, viewing_debug_cam, copy_main_on_enter, show_frustum, frustum_max_dist, show_cascades,
+	pip, pip_size, pip_corner, render_mode);
+impl DebugCameraState {
+	pub fn viewing_debug_cam(&self) -> bool { self.viewing_debug_cam }
 }
-serializer!(DebugCameraState, viewing_debug_cam);
 serializer_world!(DebugCameraState, Res<DebugCameraState>);
 
+#[derive(Reflect, Clone, Copy, PartialEq, Debug)]
+pub enum PipCorner {
+	TopLeft,
+	TopRight,
+	BottomLeft,
+	BottomRight,
+}
+serializer_enum!(PipCorner, TopLeft, TopRight, BottomLeft, BottomRight);
+
+const TOGGLE_DEBUG_CAM_KEY : KeyCode = KeyCode::KeyP; // with Shift: new debug camera at the current view
+const PREV_DEBUG_CAM_KEY : KeyCode = KeyCode::BracketLeft;
+const NEXT_DEBUG_CAM_KEY : KeyCode = KeyCode::BracketRight;
+const TOGGLE_PIP_KEY : KeyCode = KeyCode::KeyO;
+const FREEZE_CULLING_KEY : KeyCode = KeyCode::KeyK;
+const PIP_MARGIN : f32 = 8.0; // logical pixels
+
 fn update(
 	mut state: ResMut<DebugCameraState>,
 	keyboard: Res<ButtonInput<KeyCode>>,
-	main_cam: Single<(&mut Camera, &Transform), (With<MainCamera>, Without<DebugCamera>)>,
-	debug_cam: Single<(&mut Camera, &mut Transform), (With<DebugCamera>, Without<MainCamera>)>,
+	cursor: Res<CursorManager>,
+	egui_wants: Res<EguiWantsInput>,
+	window: Single<&Window, With<PrimaryWindow>>,
+	main_cam: Single<(&mut Camera, &Transform, &Frustum, &Projection, &GlobalTransform), (With<MainCamera>, Without<DebugCamera>)>,
+	mut debug_cams: Query<(Entity, &mut Camera, &mut Transform, &Flycam, Option<&Mesh3d>, Option<&MeshMaterial3d<StandardMaterial>>),
+		(With<DebugCamera>, Without<MainCamera>)>,
 	mut commands: Commands
 ) {
-	let (mut main_cam, main_transf) = main_cam.into_inner();
-	let (mut debug_cam, mut debug_transf) = debug_cam.into_inner();
+	let (mut main_cam, main_transf, main_frustum, main_proj, main_global) = main_cam.into_inner();
+	
+	let mut cams: Vec<Entity> = debug_cams.iter().map(|(e, ..)| e).collect();
+	cams.sort();
+	let mut active = state.active_cam.filter(|e| cams.contains(e)).or(cams.first().copied());
 	
-	if keyboard.just_pressed(KeyCode::KeyP) {
+	let hotkeys = !cursor::keyboard_captured_by_ui(&cursor, &egui_wants);
+	let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+	
+	if keyboard.just_pressed(TOGGLE_DEBUG_CAM_KEY) && hotkeys && shift {
+		// Copy the view, flycam settings and debug mesh of the camera we are looking through
+		let template = active.filter(|_| state.viewing_debug_cam).or(cams.first().copied())
+			.and_then(|e| debug_cams.get(e).ok());
+		let transf = match &template {
+			Some((_, _, transf, ..)) if state.viewing_debug_cam => **transf,
+			_ => *main_transf,
+		};
+		
+		let mut e = commands.spawn(debug_camera(format!("DebugFlycam {}", cams.len() + 1), transf));
+		// Already active when spawned, otherwise nothing would render this frame
+		e.entry::<Camera>().and_modify(|mut cam| cam.is_active = true);
+		if let Some((_, _, _, flycam, mesh, material)) = template {
+			let settings = flycam.serialize();
+			e.entry::<Flycam>().and_modify(move |mut f| f.deserialize(settings));
+			if let (Some(mesh), Some(material)) = (mesh, material) {
+				e.insert((mesh.clone(), material.clone()));
+			}
+		}
+		
+		// Deactivates the existing debug cameras below
+		state.active_cam = Some(e.id());
+		state.viewing_debug_cam = true;
+		active = None;
+	}
+	else if keyboard.just_pressed(TOGGLE_DEBUG_CAM_KEY) && hotkeys {
 		state.viewing_debug_cam = !state.viewing_debug_cam;
 		
-		if state.viewing_debug_cam {
-			*debug_transf = *main_transf;
+		if state.viewing_debug_cam && state.copy_main_on_enter {
+			if let Some((_, _, mut debug_transf, ..)) = active.and_then(|e| debug_cams.get_mut(e).ok()) {
+				*debug_transf = *main_transf;
+			}
 		}
 	}
 	
-	main_cam.is_active = !state.viewing_debug_cam;
-	debug_cam.is_active = state.viewing_debug_cam;
+	let cycle = keyboard.just_pressed(NEXT_DEBUG_CAM_KEY) as i32 - keyboard.just_pressed(PREV_DEBUG_CAM_KEY) as i32;
+	if cycle != 0 && hotkeys && state.viewing_debug_cam && !cams.is_empty() {
+		let i = active.and_then(|a| cams.iter().position(|e| *e == a)).unwrap_or(0);
+		let i = (i as i32 + cycle).rem_euclid(cams.len() as i32) as usize;
+		active = Some(cams[i]);
+		state.active_cam = active;
+	}
+	
+	if keyboard.just_pressed(TOGGLE_PIP_KEY) && hotkeys {
+		state.pip = !state.pip;
+	}
+	if keyboard.just_pressed(FREEZE_CULLING_KEY) && hotkeys {
+		state.frozen_frustum = match state.frozen_frustum {
+			Some(_) => None,
+			None => Some((*main_frustum, frustum_slice(main_proj, main_global, near_plane(main_proj), main_proj.far().min(state.frustum_max_dist)))),
+		};
+	}
+	
+	let pip = state.viewing_debug_cam && state.pip;
+	
+	main_cam.is_active = !state.viewing_debug_cam || pip;
+	for (e, mut debug_cam, ..) in &mut debug_cams {
+		debug_cam.is_active = state.viewing_debug_cam && Some(e) == active;
+	}
+	
+	// Render on top of the debug camera, ControlledFlycam then also lets us control the main camera while hovering it
+	main_cam.order = if pip { 1 } else { 0 };
+	let viewport = pip.then(|| pip_viewport(&window, state.pip_size, state.pip_corner)).flatten();
+	// Only touch the viewport when it actually changes (Viewport has no PartialEq)
+	let rect = |v: &Option<Viewport>| v.as_ref().map(|v| (v.physical_position, v.physical_size));
+	if rect(&main_cam.viewport) != rect(&viewport) {
+		main_cam.viewport = viewport;
+	}
+}
+
+fn pip_viewport(window: &Window, size: f32, corner: PipCorner) -> Option<Viewport> {
+	let window_size = window.physical_size();
+	let margin = (PIP_MARGIN * window.scale_factor()) as u32;
+	
+	// Same aspect as the window
+	let size = (window_size.as_vec2() * size.clamp(0.05, 1.0)).as_uvec2();
+	let free = window_size.saturating_sub(size + margin * 2);
+	if size.x == 0 || size.y == 0 {
+		return None; // minimized
+	}
+	
+	let x = match corner { PipCorner::TopLeft | PipCorner::BottomLeft => 0, _ => free.x };
+	let y = match corner { PipCorner::TopLeft | PipCorner::TopRight => 0, _ => free.y };
+	Some(Viewport {
+		physical_position: UVec2::new(x, y) + margin,
+		physical_size: size,
+		..default()
+	})
+}
+
+const FRUSTUM_COLOR : Color = Color::srgb(1.0, 1.0, 1.0);
+const FORWARD_COLOR : Color = Color::srgb(1.0, 0.3, 0.3);
+const CASCADE_COLORS : [Color; 4] = [
+	Color::srgb(0.2, 1.0, 0.2),
+	Color::srgb(0.2, 0.6, 1.0),
+	Color::srgb(1.0, 0.8, 0.2),
+	Color::srgb(1.0, 0.3, 1.0),
+];
+
+// Corners in the order of get_frustum_corners: near bottom right, top right, top left, bottom left, then the same for far
+fn draw_frustum_corners(gizmos: &mut Gizmos, corners: &[Vec3; 8], color: Color) {
+	for i in 0..4 {
+		let j = (i + 1) % 4;
+		gizmos.line(corners[i], corners[j], color);
+		gizmos.line(corners[i + 4], corners[j + 4], color);
+		gizmos.line(corners[i], corners[i + 4], color);
+	}
+}
+
+// Slice of the camera frustum between two (positive) view distances in world space
+fn frustum_slice(proj: &Projection, transf: &GlobalTransform, near: f32, far: f32) -> [Vec3; 8] {
+	// -z is forward in view space
+	proj.get_frustum_corners(-near, -far).map(|c| transf.transform_point(c.into()))
+}
+
+fn near_plane(proj: &Projection) -> f32 {
+	match proj {
+		Projection::Perspective(persp) => persp.near,
+		Projection::Orthographic(ortho) => ortho.near,
+		_ => 0.0,
+	}
+}
+
+const FROZEN_FRUSTUM_COLOR : Color = Color::srgb(0.2, 1.0, 1.0);
+
+fn apply_frozen_frustum(
+		state: Res<DebugCameraState>,
+		mut frusta: Query<&mut Frustum, Or<(With<MainCamera>, With<DebugCamera>)>>) {
+	let Some((frozen, _)) = state.frozen_frustum else { return };
+	for mut frustum in &mut frusta {
+		*frustum = frozen;
+	}
+}
+
+fn draw_main_camera_frustum(
+		state: Res<DebugCameraState>,
+		main_cam: Single<(&Projection, &GlobalTransform, &Flycam), With<MainCamera>>,
+		lights: Query<(&DirectionalLight, &CascadeShadowConfig, &GlobalTransform)>,
+		mut gizmos: Gizmos) {
+	// Visible from the main camera too, since moving it no longer changes culling
+	if let Some((_, corners)) = &state.frozen_frustum {
+		draw_frustum_corners(&mut gizmos, corners, FROZEN_FRUSTUM_COLOR);
+	}
+	
+	if !state.viewing_debug_cam {
+		return;
+	}
+	let (proj, transf, flycam) = main_cam.into_inner();
+	let far = proj.far().min(state.frustum_max_dist);
+	
+	if state.show_frustum {
+		draw_frustum_corners(&mut gizmos, &frustum_slice(proj, transf, near_plane(proj), far), FRUSTUM_COLOR);
+		
+		// Forward vector up to the focus point
+		let start = transf.translation();
+		gizmos.arrow(start, start + transf.forward() * flycam.focus_dist, FORWARD_COLOR);
+	}
+	
+	if state.show_cascades {
+		// Bevy only computes cascades for active cameras, so recompute the splits like bevy does for the main camera
+		// The boxes are the light space bounds of each split, bevy's actual cascades fit a sphere around them for stability
+		for (light, config, light_transf) in &lights {
+			if !light.shadows_enabled { continue; }
+			
+			let light_rot = light_transf.rotation();
+			let overlap_factor = 1.0 - config.overlap_proportion;
+			let near_bounds = std::iter::once(config.minimum_distance)
+				.chain(config.bounds.iter().map(|bound| overlap_factor * bound));
+			
+			for (i, (near, far)) in near_bounds.zip(config.bounds.iter().copied()).enumerate() {
+				if near >= state.frustum_max_dist { break; }
+				let color = CASCADE_COLORS[i % CASCADE_COLORS.len()];
+				let corners = frustum_slice(proj, transf, near, far.min(state.frustum_max_dist));
+				draw_frustum_corners(&mut gizmos, &corners, color.with_alpha(0.5));
+				
+				let mut min = Vec3::INFINITY;
+				let mut max = Vec3::NEG_INFINITY;
+				for c in corners {
+					let c = light_rot.inverse() * c;
+					min = min.min(c);
+					max = max.max(c);
+				}
+				let center = light_rot * ((min + max) * 0.5);
+				gizmos.cuboid(Transform::from_translation(center).with_rotation(light_rot).with_scale(max - min), color);
+			}
+		}
+	}
+}
+
+// Translation and rotation only, scale has no meaning for cameras
+struct Pose {
+	translation: [f32; 3],
+	rotation: [f32; 4],
+}
+impl Default for Pose {
+	fn default() -> Self {
+		Self {
+			translation: [0.0; 3],
+			rotation: Quat::IDENTITY.to_array(),
+		}
+	}
+}
+serializer!(Pose, translation, rotation);
+
+// All debug cameras with name, pose and flycam settings, plus which one is viewed
+// Loading reuses existing debug cameras in order, spawns missing ones and despawns extra ones
+pub struct DebugCameras;
+impl WorldSerializer for DebugCameras {
+	fn serialize(world: &mut World) -> serde_json::Value {
+		let active = world.resource::<DebugCameraState>().active_cam;
+		
+		let mut query = world.query_filtered::<(Entity, Option<&Name>, &Transform, &Flycam), With<DebugCamera>>();
+		let mut cams: Vec<_> = query.iter(world).collect();
+		cams.sort_by_key(|(e, ..)| *e);
+		
+		let list: Vec<serde_json::Value> = cams.iter().map(|(_, name, transf, flycam)| {
+			let pose = Pose { translation: transf.translation.to_array(), rotation: transf.rotation.to_array() };
+			serde_json::json!({
+				"name": name.map_or(String::new(), |n| n.to_string()),
+				"pose": pose.serialize(),
+				"flycam": flycam.serialize(),
+			})
+		}).collect();
+		
+		serde_json::json!({
+			"active": cams.iter().position(|(e, ..)| Some(*e) == active),
+			"cameras": list,
+		})
+	}
+	fn deserialize(world: &mut World, mut json: serde_json::Value) {
+		let serde_json::Value::Array(list) = json["cameras"].take() else { return };
+		
+		let mut query = world.query_filtered::<Entity, With<DebugCamera>>();
+		let mut cams: Vec<Entity> = query.iter(world).collect();
+		cams.sort();
+		
+		// Debug mesh for spawned cameras
+		let template = cams.first().map(|e| (world.get::<Mesh3d>(*e).cloned(), world.get::<MeshMaterial3d<StandardMaterial>>(*e).cloned()));
+		
+		let mut loaded = Vec::new();
+		for (i, mut cam_json) in list.into_iter().enumerate() {
+			let mut pose = Pose::default();
+			pose.deserialize(cam_json["pose"].take());
+			let transf = Transform::from_translation(Vec3::from_array(pose.translation))
+				// A zero rotation in the file would normalize to NaN
+				.with_rotation(Vec4::from_array(pose.rotation).try_normalize().map_or(Quat::IDENTITY, Quat::from_vec4));
+			let name = cam_json["name"].as_str().map_or(format!("DebugFlycam {}", i + 1), |n| n.to_string());
+			
+			let e = match cams.get(i) {
+				Some(e) => *e,
+				None => {
+					let mut e = world.spawn(debug_camera(name.clone(), transf));
+					if let Some((Some(mesh), Some(material))) = &template {
+						e.insert((mesh.clone(), material.clone()));
+					}
+					e.id()
+				}
+			};
+			
+			let mut e = world.entity_mut(e);
+			e.insert((Name::new(name), transf));
+			if let Some(mut flycam) = e.get_mut::<Flycam>() {
+				flycam.deserialize(cam_json["flycam"].take());
+			}
+			loaded.push(e.id());
+		}
+		
+		// Always keep at least one
+		if !loaded.is_empty() {
+			for e in cams.iter().skip(loaded.len()) {
+				world.despawn(*e);
+			}
+		}
+		
+		let active = json["active"].as_u64().and_then(|i| loaded.get(i as usize).copied());
+		world.resource_mut::<DebugCameraState>().active_cam = active;
+	}
+}
+
+fn debug_camera_ui(
+		mut contexts: EguiContexts,
+		mut state: ResMut<DebugCameraState>,
+		mut names: Query<(Entity, &mut Name), With<DebugCamera>>) -> Result {
+	if !state.viewing_debug_cam {
+		return Ok(());
+	}
+	
+	// Same fallback as update()
+	let active = state.active_cam.filter(|e| names.contains(*e)).or(names.iter().map(|(e, _)| e).min());
+	
+	egui::Window::new("Debug Camera").show(contexts.ctx_mut()?, |ui| {
+		if let Some((_, mut name)) = active.and_then(|e| names.get_mut(e).ok()) {
+			ui.horizontal(|ui| {
+				ui.label("Name");
+				let mut text = name.to_string();
+				if ui.text_edit_singleline(&mut text).changed() {
+					name.set(text);
+				}
+			});
+		}
+		
+		
+		egui::ComboBox::from_label("Render Mode")
+			.selected_text(state.render_mode.name())
+			.show_ui(ui, |ui| {
+				for mode in DebugRenderMode::ALL {
+					ui.selectable_value(&mut state.render_mode, mode, mode.name());
+				}
+			});
+		
+		ui.horizontal(|ui| {
+			ui.checkbox(&mut state.show_frustum, "Main Frustum");
+			ui.checkbox(&mut state.show_cascades, "Shadow Cascades");
+		});
+		ui.checkbox(&mut state.pip, "Picture-in-Picture [O]");
+		ui.checkbox(&mut state.copy_main_on_enter, "Copy main camera on enter");
+		
+		ui.label(egui::RichText::new("[P] exit, [Shift+P] new camera, [ / ] cycle, [K] freeze culling").small().weak());
+	});
+	
+	Ok(())
 }
@@ -0,0 +1,257 @@
+use bevy::prelude::*;
+use bevy::camera::visibility::RenderLayers;
+use bevy::gizmos::config::GizmoConfigStore;
+use bevy::light::NotShadowCaster;
+use bevy::mesh::skinning::SkinnedMesh;
+use bevy::pbr::wireframe::{ Wireframe, WireframePlugin };
+use bevy::platform::collections::HashMap;
+use bevy::render::render_resource::{ AsBindGroup, ShaderType };
+use bevy::shader::ShaderRef;
+use crate::phases::Phase;
+use crate::serialization::*;
+use crate::debug_camera::{ DebugCamera, DebugCameraState };
+use crate::particle_render::ParticleInstances;
+
+// Debug visualizations while viewing from the debug camera
+// Entity materials are never touched, instead every mesh gets a DebugRenderCopy child with the debug material on DEBUG_LAYER
+// and the debug camera only renders that layer, so the picture-in-picture main camera keeps the normal view
+pub struct DebugRenderPlugin;
+impl Plugin for DebugRenderPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			// Needs POLYGON_MODE_LINE, which wgpu enables if the adapter supports it, otherwise the plugin just warns
+			.add_plugins((WireframePlugin::default(), MaterialPlugin::<DebugViewMaterial>::default()))
+			.insert_resource(DebugRenderAssets::default())
+			// After debug_camera::update decided what we are viewing
+			.add_systems(Update, (apply_render_mode, extend_to_debug_layer).after(Phase::CameraUpdate));
+	}
+}
+
+const SHADER_PATH : &str = "shaders/debug_view.wgsl";
+// Only the debug camera renders this layer, while a mode other than Normal is shown
+pub const DEBUG_LAYER : usize = 7;
+
+#[derive(Reflect, Clone, Copy, PartialEq, Debug)]
+pub enum DebugRenderMode {
+	Normal,
+	Wireframe, // overlay on top of the normal materials
+	FlatNormals,
+	UvChecker,
+	Unlit, // albedo only
+	ShadowOnly, // lighting and shadows on white
+	Depth,
+}
+serializer_enum!(DebugRenderMode, Normal, Wireframe, FlatNormals, UvChecker, Unlit, ShadowOnly, Depth);
+impl DebugRenderMode {
+	pub const ALL : [DebugRenderMode; 7] = [
+		DebugRenderMode::Normal,
+		DebugRenderMode::Wireframe,
+		DebugRenderMode::FlatNormals,
+		DebugRenderMode::UvChecker,
+		DebugRenderMode::Unlit,
+		DebugRenderMode::ShadowOnly,
+		DebugRenderMode::Depth,
+	];
+	
+	pub fn name(&self) -> &'static str {
+		match self {
+			DebugRenderMode::Normal => "Normal",
+			DebugRenderMode::Wireframe => "Wireframe",
+			DebugRenderMode::FlatNormals => "Flat Normals",
+			DebugRenderMode::UvChecker => "UV Checker",
+			DebugRenderMode::Unlit => "Unlit Albedo",
+			DebugRenderMode::ShadowOnly => "Shadow Only",
+			DebugRenderMode::Depth => "Depth",
+		}
+	}
+	
+	// Mode of the debug view shader
+	fn view_mode(&self) -> Option<u32> {
+		match self {
+			DebugRenderMode::FlatNormals => Some(0),
+			DebugRenderMode::UvChecker => Some(1),
+			DebugRenderMode::Depth => Some(2),
+			_ => None,
+		}
+	}
+}
+
+#[derive(ShaderType, Clone, Copy, Debug)]
+struct DebugViewSettings {
+	mode: u32,
+	checker_scale: f32,
+	depth_range: f32,
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Clone, Debug)]
+pub struct DebugViewMaterial {
+	#[uniform(0)]
+	settings: DebugViewSettings,
+}
+impl Material for DebugViewMaterial {
+	fn fragment_shader() -> ShaderRef {
+		SHADER_PATH.into()
+	}
+}
+
+// Mesh of the parent entity as seen by the debug camera
+#[derive(Component)]
+pub struct DebugRenderCopy;
+
+#[derive(Resource)]
+struct DebugRenderAssets {
+	applied: DebugRenderMode,
+	view_materials: HashMap<u32, Handle<DebugViewMaterial>>,
+	// Per original material, cleared when the mode changes
+	variants: HashMap<AssetId<StandardMaterial>, Handle<StandardMaterial>>,
+	// Original entity -> copy, cleared when the mode changes
+	copies: HashMap<Entity, Entity>,
+}
+impl Default for DebugRenderAssets {
+	fn default() -> Self {
+		Self {
+			applied: DebugRenderMode::Normal,
+			view_materials: HashMap::default(),
+			variants: HashMap::default(),
+			copies: HashMap::default(),
+		}
+	}
+}
+
+fn make_variant(original: &StandardMaterial, mode: DebugRenderMode) -> StandardMaterial {
+	let mut mat = original.clone();
+	match mode {
+		DebugRenderMode::Unlit => {
+			mat.unlit = true;
+		}
+		DebugRenderMode::ShadowOnly => {
+			mat.base_color = Color::WHITE;
+			mat.base_color_texture = None;
+			mat.emissive = LinearRgba::BLACK;
+			mat.emissive_texture = None;
+			mat.metallic = 0.0;
+			mat.perceptual_roughness = 1.0;
+			mat.reflectance = 0.0;
+		}
+		_ => {}
+	}
+	mat
+}
+
+fn apply_render_mode(
+		state: Res<DebugCameraState>,
+		mut assets: ResMut<DebugRenderAssets>,
+		mut std_materials: ResMut<Assets<StandardMaterial>>,
+		mut view_materials: ResMut<Assets<DebugViewMaterial>>,
+		debug_cams: Query<(Entity, Has<RenderLayers>), With<DebugCamera>>,
+		originals: Query<(Entity, Ref<Mesh3d>, Ref<MeshMaterial3d<StandardMaterial>>, Option<&RenderLayers>, Option<&SkinnedMesh>, Has<NotShadowCaster>),
+			Without<DebugRenderCopy>>,
+		mut commands: Commands) {
+	
+	let mode = if state.viewing_debug_cam() { state.render_mode } else { DebugRenderMode::Normal };
+	let copying = mode != DebugRenderMode::Normal;
+	
+	for (e, has_layers) in &debug_cams {
+		if copying && !has_layers {
+			commands.entity(e).insert(RenderLayers::layer(DEBUG_LAYER));
+		}
+		else if !copying && has_layers {
+			commands.entity(e).remove::<RenderLayers>();
+		}
+	}
+	
+	if mode != assets.applied {
+		for (_, copy) in assets.copies.drain() {
+			commands.entity(copy).try_despawn();
+		}
+		// Unused variants get freed once their handles are dropped
+		assets.variants.clear();
+		assets.applied = mode;
+	}
+	if !copying {
+		return;
+	}
+	
+	// Copies despawn along with their original, but not if it just lost its mesh or material
+	assets.copies.retain(|original, copy| {
+		let keep = originals.contains(*original);
+		if !keep {
+			commands.entity(*copy).try_despawn();
+		}
+		keep
+	});
+	
+	let view_mat = mode.view_mode().map(|view_mode| {
+		assets.view_materials.entry(view_mode).or_insert_with(|| view_materials.add(DebugViewMaterial {
+			settings: DebugViewSettings { mode: view_mode, checker_scale: 8.0, depth_range: 100.0 },
+		})).clone()
+	});
+	
+	let main_layers = RenderLayers::default();
+	for (e, mesh, material, layers, skinned, no_shadows) in &originals {
+		// Only what the main camera would show
+		if layers.is_some_and(|l| !l.intersects(&main_layers)) { continue; }
+		
+		// Follow mesh and material changes (ex. the particle palette, or do_very_specific_thing_to_object after a scene loaded)
+		let existing = assets.copies.get(&e).copied();
+		if existing.is_some() && !mesh.is_changed() && !material.is_changed() { continue; }
+		
+		let std_mat = match mode {
+			DebugRenderMode::Wireframe => Some(material.0.clone()),
+			DebugRenderMode::Unlit | DebugRenderMode::ShadowOnly => match assets.variants.get(&material.id()) {
+				Some(variant) => Some(variant.clone()),
+				None => {
+					// Not loaded yet, try again next frame
+					let Some(variant) = std_materials.get(&material.0).map(|m| make_variant(m, mode)) else { continue };
+					let variant = std_materials.add(variant);
+					assets.variants.insert(material.id(), variant.clone());
+					Some(variant)
+				}
+			},
+			_ => None,
+		};
+		
+		let mut copy = match existing {
+			Some(copy) => commands.entity(copy),
+			None => commands.spawn((DebugRenderCopy, Transform::default(), RenderLayers::layer(DEBUG_LAYER), ChildOf(e))),
+		};
+		copy.insert(mesh.clone());
+		match (std_mat, &view_mat) {
+			(Some(std_mat), _) => { copy.insert(MeshMaterial3d(std_mat)); }
+			(None, Some(view_mat)) => { copy.insert(MeshMaterial3d(view_mat.clone())); }
+			_ => {}
+		}
+		if mode == DebugRenderMode::Wireframe {
+			copy.insert(Wireframe);
+		}
+		if let Some(skinned) = skinned {
+			copy.insert(skinned.clone());
+		}
+		if no_shadows {
+			copy.insert(NotShadowCaster);
+		}
+		assets.copies.insert(e, copy.id());
+	}
+}
+
+// Lights, gizmos and instanced particles on the main layer also show up on DEBUG_LAYER, which only the copies are on otherwise
+fn extend_to_debug_layer(
+		mut config_store: ResMut<GizmoConfigStore>,
+		shared: Query<(Entity, Option<&RenderLayers>),
+			Or<(With<DirectionalLight>, With<PointLight>, With<SpotLight>, With<ParticleInstances>)>>,
+		mut commands: Commands) {
+	let main_layers = RenderLayers::default();
+	let debug_layers = RenderLayers::layer(DEBUG_LAYER);
+	
+	for (_, config, _) in config_store.iter_mut() {
+		if config.render_layers.intersects(&main_layers) && !config.render_layers.intersects(&debug_layers) {
+			config.render_layers = config.render_layers.clone().with(DEBUG_LAYER);
+		}
+	}
+	for (e, layers) in &shared {
+		let layers = layers.unwrap_or(&main_layers);
+		if layers.intersects(&main_layers) && !layers.intersects(&debug_layers) {
+			commands.entity(e).insert(layers.clone().with(DEBUG_LAYER));
+		}
+	}
+}
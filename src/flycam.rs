@@ -1,12 +1,19 @@
 use bevy::{
 	prelude::*,
 	math,
+	camera::ScalingMode,
 	input::mouse::{ MouseMotion, MouseWheel, MouseScrollUnit },
-	window::{ CursorGrabMode, CursorIcon, CursorOptions, SystemCursorIcon },
+	window::PrimaryWindow,
+	camera::{ NormalizedRenderTarget, primitives::Aabb },
 };
 use core::f32;
 use std::fmt;
 use crate::app_control::WindowSettings;
+use crate::cursor::{ self, CursorManager };
+use crate::debug_render::DebugRenderCopy;
+use crate::particles::{ Particle, ParticleBuffers };
+use crate::selection;
+use bevy_egui::input::EguiWantsInput;
 use crate::phases::Phase;
 use crate::serialization::*;
 
@@ -15,24 +22,65 @@ pub struct FlycamPlugin;
 impl Plugin for FlycamPlugin {
 	fn build(&self, app: &mut App) {
 		app
-			.add_systems(Update, update_cursor.before(update_camera))
-			.add_systems(Update, update_camera.in_set(Phase::CameraUpdate));
+			.insert_resource(FlycamInput::default())
+			.insert_resource(ScrollCalibration::default())
+			.insert_resource(ControlledFlycam::default())
+			.add_systems(Update, (
+				gather_input,
+				select_controlled_flycam,
+				update_camera.after(gather_input).after(select_controlled_flycam),
+			).in_set(Phase::CameraUpdate));
 	}
 }
 
-const MOUSELOOK_BTN : MouseButton = MouseButton::Middle;
+// Only used in 6-DOF mode, Q/E are already used for moving up/down
+// Ignored while Ctrl is held, Ctrl+Z is undo
+const ROLL_LEFT_KEY : KeyCode = KeyCode::KeyZ;
+const ROLL_RIGHT_KEY : KeyCode = KeyCode::KeyC;
+const LEVEL_HORIZON_KEY : KeyCode = KeyCode::KeyX;
+// Numpad view controls like in Blender, Ctrl snaps to the opposite side
+const TOGGLE_ORTHO_KEY : KeyCode = KeyCode::Numpad5;
+const FRONT_VIEW_KEY : KeyCode = KeyCode::Numpad1;
+const RIGHT_VIEW_KEY : KeyCode = KeyCode::Numpad3;
+const TOP_VIEW_KEY : KeyCode = KeyCode::Numpad7;
+const JUMP_KEY : KeyCode = KeyCode::Space;
 
 #[derive(Component, Reflect)]
 #[require(Transform, Camera3d, Camera)]
 #[reflect(Component)]
 pub struct Flycam {
 	pub move_planar : bool,
+	// Free rotation including roll, without pitch limits
+	pub six_dof : bool,
+	pub roll_speed : f32,
 	pub vfov_multiplied_sensitivity : bool,
 	pub mouse_sens : f32,
+	// Multipliers on top of mouse_sens
+	pub mouse_sens_x : f32,
+	pub mouse_sens_y : f32,
+	pub invert_y : bool,
+	// Sensitivity grows with mouse speed: 1 + mouse_accel * (dots/ms), capped at mouse_accel_max (0 disables)
+	pub mouse_accel : f32,
+	pub mouse_accel_max : f32,
+	// Low-pass filter of the mouse motion rate in seconds, smooths out uneven polling vs frame rate (0 disables)
+	pub mouse_smoothing : f32,
+	mouse_rate : Vec2, // filtered dots/s
 	
 	pub default_vfov : f32,
 	pub vfov_target : f32,
 	pub vfov_smooth : f32,
+	// Projection follows this, so it can be persisted, toggle with TOGGLE_ORTHO_KEY
+	pub orthographic : bool,
+	// Visible height in world units when orthographic (OrthographicProjection::scale with FixedVertical(1.0))
+	pub ortho_scale_target : f32,
+	
+	// Distance to the point we are looking at
+	// Used to preserve apparent scale when switching projection and as the pivot for view snapping
+	pub focus_dist : f32,
+	
+	// Smooth transition after frame_bounds, cancelled by moving
+	pub frame_smooth : f32,
+	frame_target : Option<Vec3>,
 	
 	pub zoom_speed : f32,
 	
@@ -41,6 +89,29 @@ pub struct Flycam {
 	pub max_speed : f32,
 	pub speedup_factor : f32,
 	pub fast_multiplier : f32,
+	
+	// Inertial movement, velocity approaches the WASD target velocity instead of snapping to it
+	// frame rate independent like vfov_smooth, the rates are in 1/s
+	pub smooth_movement : bool,
+	pub accel : f32, // while moving
+	pub decel : f32, // after releasing, so this is the damping when coasting
+	pub velocity : Vec3,
+	
+	// Keep the camera out of mesh bounding boxes (including the ground plane)
+	pub collision : bool,
+	pub collision_radius : f32,
+	// Player style movement with gravity and jumping, always planar and colliding
+	pub walk : bool,
+	pub eye_height : f32,
+	pub gravity : f32,
+	pub jump_speed : f32,
+	grounded : bool,
+	
+	// Mouselook rotates a target, which the actual rotation smoothly follows
+	pub smooth_rotation : bool,
+	pub rotation_smooth : f32,
+	rotation_target : Quat,
+	rotation_applied : Quat, // to detect other systems moving the camera
 }
 impl Flycam {
 	pub fn new(transf: Transform) -> (Transform, Camera3d, Projection, Flycam) {
@@ -56,15 +127,30 @@ impl Flycam {
 			}),
 			Flycam {
 				move_planar: true,
+				six_dof: false,
+				roll_speed: 90_f32.to_radians(),
 				vfov_multiplied_sensitivity: true,
 				// if vfov_multiplied_sensitivity == false:
 				//mouse_sens: 120_f32.to_radians() / 1000.0, // degrees / mouse 'dots'
 				// if vfov_multiplied_sensitivity == true:
 				mouse_sens: 2.0 / 1000.0, // screen heights / mouse 'dots'
+				mouse_sens_x: 1.0,
+				mouse_sens_y: 1.0,
+				invert_y: false,
+				mouse_accel: 0.0,
+				mouse_accel_max: 4.0,
+				mouse_smoothing: 0.0,
+				mouse_rate: Vec2::ZERO,
 				
 				default_vfov: vfov,
 				vfov_target: vfov,
 				vfov_smooth: 25.0,
+				orthographic: false,
+				ortho_scale_target: ortho_height(vfov, 5.0),
+				focus_dist: 5.0,
+				
+				frame_smooth: 10.0,
+				frame_target: None,
 				zoom_speed: 1.5,
 				
 				speed: 4.0,
@@ -73,50 +159,206 @@ impl Flycam {
 				max_speed: 1000000.0,
 				speedup_factor: 2.0,
 				fast_multiplier: 4.0,
+				
+				smooth_movement: false,
+				accel: 8.0,
+				decel: 4.0,
+				velocity: Vec3::ZERO,
+				
+				collision: false,
+				collision_radius: 0.2,
+				walk: false,
+				eye_height: 1.7,
+				gravity: 9.81,
+				jump_speed: 4.5,
+				grounded: false,
+				
+				smooth_rotation: false,
+				rotation_smooth: 30.0,
+				rotation_target: transf.rotation,
+				rotation_applied: transf.rotation,
 			}
 		)
 	}
 }
-serializer!(Flycam, move_planar, mouse_sens, default_vfov, base_speed, speedup_factor);
+serializer!(Flycam, move_planar, six_dof, roll_speed, mouse_sens,
+	mouse_sens_x, mouse_sens_y, invert_y, mouse_accel, mouse_accel_max, mouse_smoothing,
+	default_vfov, orthographic, ortho_scale_target, focus_dist,
+	base_speed, speedup_factor,
+	smooth_movement, accel, decel, smooth_rotation, rotation_smooth,
+	collision, collision_radius, walk, eye_height, gravity, jump_speed);
+
+// Scrolling is weird, normally on windows each scroll tick results in +-120 (pixels?)
+// Supposedly there are also smooth scrolling mice, which would presumably return smaller increments?
+// Here we seem to get Line with +-1 (or more if the OS combined events)
+// But on wasm supposedly it returns +-100
+// MouseWheel has no device id, so we can't calibrate per device, instead each MouseScrollUnit gets its own scale
+// which in practice separates notched wheels (Line) from touchpads and smooth scrolling (Pixel)
+#[derive(Resource, Reflect, Clone)]
+#[reflect(Resource)]
+pub struct ScrollCalibration {
+	// Lines per Line event
+	pub line_scale : f32,
+	// Pixels that count as one line
+	pub pixels_per_line : f32,
+	pub invert : bool,
+}
+impl Default for ScrollCalibration {
+	fn default() -> Self {
+		Self {
+			line_scale: 1.0,
+			pixels_per_line: 100.0,
+			invert: false,
+		}
+	}
+}
+serializer!(ScrollCalibration, line_scale, pixels_per_line, invert);
+serializer_world!(ScrollCalibration, Res<ScrollCalibration>);
+
+// Mouse input read once per frame, MessageReaders consume messages, so cameras can't each read them
+#[derive(Resource, Default)]
+pub struct FlycamInput {
+	pub mouse_motion: Vec2,
+	// Calibrated, see ScrollCalibration
+	pub scroll_lines: f32,
+}
+
+// Which flycam receives input, only one camera is controlled at a time
+// (ex. split-screen, picture-in-picture or multiple windows)
+#[derive(Resource, Default, Reflect)]
+#[reflect(Resource)]
+pub struct ControlledFlycam {
+	// Explicitly controlled camera, if None control follows the active camera under the cursor
+	pub camera: Option<Entity>,
+	// Resolved each frame
+	current: Option<Entity>,
+}
+impl ControlledFlycam {
+	pub fn current(&self) -> Option<Entity> { self.current }
+}
 
 fn wrap(x: f32, y: f32) -> f32 {
 	((x % y) + y) % y
 }
-fn get_mouse_scroll_delta(mouse_wheel: &mut MessageReader<MouseWheel>) -> f32 {
-	let mut total_lines : f32 = 0.0;
+fn gather_input(
+		mut mouse_motion: MessageReader<MouseMotion>,
+		mut mouse_wheel: MessageReader<MouseWheel>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		calibration: Res<ScrollCalibration>,
+		mut input: ResMut<FlycamInput>) {
+	input.mouse_motion = mouse_motion.read().map(|e| e.delta).sum();
+	input.scroll_lines = 0.0;
 	for event in mouse_wheel.read() {
-		//match event.unit {
-		//	MouseScrollUnit::Line => println!("Scroll (Lines): {:8.3}", event.y),
-		//	MouseScrollUnit::Pixel => println!("Scroll (Pixels): {:8.3}", event.y),
-		//}
+		input.scroll_lines += match event.unit {
+			MouseScrollUnit::Line => event.y * calibration.line_scale,
+			MouseScrollUnit::Pixel => event.y / calibration.pixels_per_line.max(0.001),
+		};
+	}
+	if calibration.invert {
+		input.scroll_lines = -input.scroll_lines;
+	}
+	
+	// Scrolling an egui window
+	if cursor::pointer_captured_by_ui(&cursor, &egui_wants) {
+		input.scroll_lines = 0.0;
+	}
+}
+
+fn select_controlled_flycam(
+		cursor: Res<CursorManager>,
+		primary_window: Query<Entity, With<PrimaryWindow>>,
+		windows: Query<&Window>,
+		cameras: Query<(Entity, &Camera), With<Flycam>>,
+		mut control: ResMut<ControlledFlycam>) {
+	
+	let is_active = |e: Entity| cameras.get(e).is_ok_and(|(_, cam)| cam.is_active);
+	
+	if let Some(explicit) = control.camera {
+		control.current = Some(explicit).filter(|e| is_active(*e));
+		return;
+	}
+	
+	// Keep controlling the same camera while mouselooking, even if the cursor leaves its viewport
+	if cursor.is_mouselook() && control.current.is_some_and(is_active) {
+		return;
+	}
+	
+	let mut hovered: Option<(Entity, isize)> = None;
+	for (entity, cam) in &cameras {
+		if !cam.is_active { continue; }
+		
+		let Some(NormalizedRenderTarget::Window(window_ref)) = cam.target.normalize(primary_window.single().ok()) else { continue };
+		let Some(cursor) = windows.get(window_ref.entity()).ok().and_then(|w| w.cursor_position()) else { continue };
+		let Some(rect) = cam.logical_viewport_rect() else { continue };
 		
-		// Scrolling is weird, normally on windows each scroll tick results in +-120 (pixels?)
-		// Supposedly there are also smooth scrolling mice, which would presumably return smaller increments?
-		// Here we seem to get Line with +-1 (or more if the OS combined events)
-		// But on wasm supposedly it returns +-100
-		// What would other OSes or smooth scrolling mice result in?
-		// This is my best guess at what makes sense
-		total_lines += match event.unit {
-			MouseScrollUnit::Line => event.y,
-			MouseScrollUnit::Pixel => event.y / 100.0,
+		// Topmost camera wins, ex. picture-in-picture over the main view
+		if rect.contains(cursor) && hovered.is_none_or(|(_, order)| cam.order > order) {
+			hovered = Some((entity, cam.order));
 		}
 	}
-	total_lines
+	
+	if let Some((entity, _)) = hovered {
+		control.current = Some(entity);
+	}
+	// Cursor outside of all viewports, keep the last camera if still active
+	else if !control.current.is_some_and(is_active) {
+		control.current = cameras.iter().find(|(_, cam)| cam.is_active).map(|(e, _)| e);
+	}
+}
+
+impl Flycam {
+	// Mouse motion in dots after smoothing and acceleration, with per axis sensitivity and invert applied
+	fn mouse_delta(&mut self, dt: f32, motion: Vec2) -> Vec2 {
+		let mut delta = motion;
+		
+		if self.mouse_smoothing > 0.0 && dt > 0.0 {
+			// Filter the rate instead of the per frame delta, so the result does not depend on frame rate
+			let rate = motion / dt;
+			self.mouse_rate.smooth_nudge(&rate, 1.0 / self.mouse_smoothing, dt);
+			delta = self.mouse_rate * dt;
+		}
+		else {
+			self.mouse_rate = Vec2::ZERO;
+		}
+		
+		if self.mouse_accel > 0.0 && dt > 0.0 {
+			let dots_per_ms = delta.length() / (dt * 1000.0);
+			delta *= (1.0 + self.mouse_accel * dots_per_ms).min(self.mouse_accel_max.max(1.0));
+		}
+		
+		let invert = if self.invert_y { -1.0 } else { 1.0 };
+		delta * Vec2::new(self.mouse_sens_x, self.mouse_sens_y * invert)
+	}
 }
 fn get_mouselook_sensitivity(flycam: &Flycam, proj: &Projection) -> f32 {
 	if flycam.vfov_multiplied_sensitivity {
-		if let Projection::Perspective(persp) = proj {
-			return flycam.mouse_sens * persp.fov;
+		match proj {
+			Projection::Perspective(persp) => return flycam.mouse_sens * persp.fov,
+			// Use the fov that would show the same area at focus_dist
+			Projection::Orthographic(ortho) => return flycam.mouse_sens * ortho_equivalent_vfov(ortho.area.height(), flycam.focus_dist),
+			_ => {}
 		}
 	}
 	
 	return flycam.mouse_sens;
 }
 
+const MIN_VFOV : f32 = 0.1_f32 * (f32::consts::PI / 180.0);
+const MAX_VFOV : f32 = 170.0_f32 * (f32::consts::PI / 180.0);
+
+// Visible height at dist for a perspective camera
+fn ortho_height(vfov: f32, dist: f32) -> f32 {
+	2.0 * dist * (vfov * 0.5).tan()
+}
+fn ortho_equivalent_vfov(height: f32, dist: f32) -> f32 {
+	(2.0 * (height * 0.5 / dist).atan()).clamp(MIN_VFOV, MAX_VFOV)
+}
+
 fn zoom(
 		time: &Res<Time>,
-		keyboard: &Res<ButtonInput<KeyCode>>,
-		mouse_wheel: &mut MessageReader<MouseWheel>,
+		keyboard: &ButtonInput<KeyCode>,
+		input: &FlycamInput,
 		flycam: &mut Flycam, proj: &mut Projection) {
 	// key zoom
 	let mut zoom_dir: f32 = 0.0;
@@ -128,87 +370,236 @@ fn zoom(
 	// mousewheel zoom
 	if zoom_delta == 0.0 {
 		// 0.125 to kinda bring it in line with keyboard based zooming
-		zoom_delta = 0.125*get_mouse_scroll_delta(mouse_wheel);
+		zoom_delta = 0.125*input.scroll_lines;
 	}
 	
-	// F + Mousewheel or +/- Zooms FOV
-	if keyboard.pressed(KeyCode::KeyF) {
-		let mut fov = flycam.vfov_target;
-		fov = 2.0_f32.powf(fov.log2() - zoom_delta);
+	// F + Mousewheel or +/- Zooms FOV, in ortho they always zoom since that's the only way to get closer
+	let ortho = matches!(proj, Projection::Orthographic(_));
+	if keyboard.pressed(KeyCode::KeyF) || ortho {
+		let reset = keyboard.pressed(KeyCode::KeyF) && keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) && zoom_delta != 0.0;
 		
-		let min_vfov = 0.1_f32.to_radians();
-		let max_vfov = 170.0_f32.to_radians();
-		fov = fov.clamp(min_vfov, max_vfov);
-		
-		if keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) && zoom_delta != 0.0 {
-			fov = flycam.default_vfov;
+		if ortho {
+			let mut scale = flycam.ortho_scale_target;
+			scale = 2.0_f32.powf(scale.log2() - zoom_delta);
+			scale = scale.clamp(0.001, 1000000.0);
+			
+			if reset {
+				scale = ortho_height(flycam.default_vfov, flycam.focus_dist);
+			}
+			flycam.ortho_scale_target = scale;
+		}
+		else {
+			let mut fov = flycam.vfov_target;
+			fov = 2.0_f32.powf(fov.log2() - zoom_delta);
+			fov = fov.clamp(MIN_VFOV, MAX_VFOV);
+			
+			if reset {
+				fov = flycam.default_vfov;
+			}
+			flycam.vfov_target = fov;
 		}
-		flycam.vfov_target = fov;
 	}
 	// Otherwise Mousewheel or +/- Zooms FOV changes base speed (later clamped in movement)
 	else {
 		flycam.base_speed = 2.0_f32.powf(flycam.base_speed.log2() + zoom_delta);
 	}
 	
-	if let Projection::Perspective(persp) = proj {
+	match proj {
+		Projection::Perspective(persp) => {
+			persp.fov.smooth_nudge(&flycam.vfov_target, flycam.vfov_smooth, time.delta_secs());
+			
+			//println!("VFov: {:8.3}", persp.fov.to_degrees());
+		}
+		Projection::Orthographic(ortho) => {
+			ortho.scale.smooth_nudge(&flycam.ortho_scale_target, flycam.vfov_smooth, time.delta_secs());
+		}
+		_ => {}
+	}
+}
+
+// Move back along the view direction until the bounds fill the view
+// Also makes the bounds center the focus point
+pub fn frame_bounds(transf: &Transform, flycam: &mut Flycam, proj: &Projection, aspect: f32, min: Vec3, max: Vec3) {
+	let margin = 1.1;
+	let center = (min + max) * 0.5;
+	// bounding sphere, so the result does not depend on the view direction
+	let radius = ((max - min).length() * 0.5).max(0.01) * margin;
+	
+	// Fit the smaller of vertical and horizontal fov
+	let half_vfov = flycam.vfov_target * 0.5;
+	let half_hfov = (half_vfov.tan() * aspect).atan();
+	let dist = radius / half_vfov.min(half_hfov).sin();
+	
+	if let Projection::Orthographic(_) = proj {
+		// visible height, but also fit the width for tall viewports
+		flycam.ortho_scale_target = 2.0 * radius * (1.0 / aspect).max(1.0);
+	}
+	
+	flycam.focus_dist = dist;
+	flycam.frame_target = Some(center - transf.forward() * dist);
+	flycam.velocity = Vec3::ZERO;
+}
+
+fn toggle_projection(flycam: &mut Flycam, proj: &mut Projection) {
+	// Show the same area at focus_dist, so things we are looking at keep their apparent size
+	match proj {
+		Projection::Perspective(persp) => {
+			flycam.ortho_scale_target = ortho_height(persp.fov, flycam.focus_dist);
+		}
+		Projection::Orthographic(ortho) => {
+			// area already includes scale and works for any scaling_mode
+			flycam.vfov_target = ortho_equivalent_vfov(ortho.area.height(), flycam.focus_dist);
+		}
+		_ => {}
+	}
+	flycam.orthographic = !flycam.orthographic;
+	apply_projection(flycam, proj);
+}
+// Switch projection if flycam.orthographic changed (toggled or loaded from settings)
+fn apply_projection(flycam: &Flycam, proj: &mut Projection) {
+	match proj {
+		Projection::Perspective(persp) if flycam.orthographic => {
+			*proj = Projection::Orthographic(OrthographicProjection {
+				scaling_mode: ScalingMode::FixedVertical { viewport_height: 1.0 },
+				scale: flycam.ortho_scale_target,
+				far: persp.far,
+				..OrthographicProjection::default_3d()
+			});
+		}
+		Projection::Orthographic(ortho) if !flycam.orthographic => {
+			*proj = Projection::Perspective(PerspectiveProjection {
+				fov: flycam.vfov_target,
+				near: 0.1,
+				far: ortho.far,
+				..default()
+			});
+		}
+		_ => {}
+	}
+}
+// Axis aligned views, rotating around the focus point
+fn view_controls(
+		keyboard: &ButtonInput<KeyCode>,
+		transf: &mut Transform, flycam: &mut Flycam, proj: &mut Projection) {
+	
+	if keyboard.just_pressed(TOGGLE_ORTHO_KEY) {
+		toggle_projection(flycam, proj);
+	}
+	
+	let flip = if keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) { -1.0 } else { 1.0 };
+	
+	// (forward, up)
+	let view = if keyboard.just_pressed(FRONT_VIEW_KEY) {
+		Some((Vec3::NEG_Z * flip, Vec3::Y))
+	} else if keyboard.just_pressed(RIGHT_VIEW_KEY) {
+		Some((Vec3::NEG_X * flip, Vec3::Y))
+	} else if keyboard.just_pressed(TOP_VIEW_KEY) {
+		Some((Vec3::NEG_Y * flip, Vec3::NEG_Z * flip))
+	} else {
+		None
+	};
+	
+	if let Some((forward, up)) = view {
+		let focus = transf.translation + transf.forward() * flycam.focus_dist;
 		
-		persp.fov.smooth_nudge(&flycam.vfov_target, flycam.vfov_smooth, time.delta_secs());
+		transf.look_to(forward, up);
+		transf.translation = focus - forward * flycam.focus_dist;
 		
-		//println!("VFov: {:8.3}", persp.fov.to_degrees());
+		// snap instantly, even with smooth_rotation
+		flycam.rotation_target = transf.rotation;
+		flycam.rotation_applied = transf.rotation;
+		flycam.velocity = Vec3::ZERO;
 	}
 }
 fn mouselook(
 		time: &Res<Time>,
-		keyboard: &Res<ButtonInput<KeyCode>>,
-		mouse: &Res<ButtonInput<MouseButton>>,
-		mut mouse_motion: &mut MessageReader<MouseMotion>,
-		cursor_options: &CursorOptions,
-		transf: &mut Transform, flycam: &Flycam, proj: &Projection) {
-	
-	let pitch_min = (-90.0_f32 + 5.0).to_radians();
-	let pitch_max = ( 90.0_f32 - 5.0).to_radians();
+		keyboard: &ButtonInput<KeyCode>,
+		input: &FlycamInput,
+		mouselooking: bool,
+		transf: &mut Transform, flycam: &mut Flycam, proj: &Projection) {
 	
-	let euler = EulerRot::YXZ;
-	let (mut yaw, mut pitch, mut roll) = transf.rotation.to_euler(euler);
+	// Something else moved the camera (ex. debug camera copying the main camera), don't smooth back
+	if transf.rotation != flycam.rotation_applied {
+		flycam.rotation_target = transf.rotation;
+	}
 	
-	if mouse.pressed(MOUSELOOK_BTN) || cursor_options.grab_mode != CursorGrabMode::None {
+	let mut look_delta = Vec2::ZERO;
+	if mouselooking {
 		let sens = get_mouselook_sensitivity(flycam, proj);
-		for event in mouse_motion.read() {
-			// NOTE: For this camera it makes sense to scale mouselook with fov
-			// This is not always the case but would fit an FPS games
-			// where muscle memory likely works based on distances on screen (which do depend on fov if zoomed in)
-			yaw   -= event.delta.x * sens;
-			pitch -= event.delta.y * sens;
-		}
+		// NOTE: For this camera it makes sense to scale mouselook with fov
+		// This is not always the case but would fit an FPS games
+		// where muscle memory likely works based on distances on screen (which do depend on fov if zoomed in)
+		look_delta -= flycam.mouse_delta(time.delta_secs(), input.mouse_motion) * sens;
+	}
+	else {
+		// Don't let smoothing carry over into the next mouselook
+		flycam.mouse_rate = Vec2::ZERO;
 	}
-	// due to Quat.to_euler, we seem to automatically get a [-180, 180] wrapping for yaw and roll, which is good enough
-	//yaw = wrap(yaw, f32::consts::TAU); // wrap into [0, 360deg] range
-	pitch = pitch.clamp(pitch_min, pitch_max);
 	
-	let enable_roll = false;
-	if enable_roll {
+	if flycam.six_dof {
+		let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
 		let mut roll_dir = 0.0_f32;
-		let roll_speed = 90_f32.to_radians();
-		if keyboard.pressed(KeyCode::KeyQ) { roll_dir += 1.0; }
-		if keyboard.pressed(KeyCode::KeyE) { roll_dir -= 1.0; }
+		if keyboard.pressed(ROLL_LEFT_KEY) && !ctrl { roll_dir += 1.0; }
+		if keyboard.pressed(ROLL_RIGHT_KEY) && !ctrl { roll_dir -= 1.0; }
+		let roll = roll_dir * (time.delta_secs() * flycam.roll_speed);
 		
-		roll -= roll_dir * (time.delta_secs() * roll_speed);
+		// Rotate around local axes directly, no euler angles means no pitch clamping or gimbal lock
+		// and mouse x always yaws around the camera's up, even when upside down
+		let local = Quat::from_rotation_y(look_delta.x)
+		          * Quat::from_rotation_x(look_delta.y)
+		          * Quat::from_rotation_z(roll);
+		flycam.rotation_target = (flycam.rotation_target * local).normalize();
+		
+		if keyboard.just_pressed(LEVEL_HORIZON_KEY) && !ctrl {
+			flycam.rotation_target = level_horizon(flycam.rotation_target);
+		}
 	}
 	else {
-		roll = 0.0;
+		let pitch_min = (-90.0_f32 + 5.0).to_radians();
+		let pitch_max = ( 90.0_f32 - 5.0).to_radians();
+		
+		let euler = EulerRot::YXZ;
+		let (mut yaw, mut pitch, roll) = flycam.rotation_target.to_euler(euler);
+		
+		// Avoid euler roundtrip when idle, top/bottom views are at the singularity
+		if look_delta != Vec2::ZERO || roll.abs() > 0.0001 {
+			let prev_pitch = pitch;
+			
+			yaw   += look_delta.x;
+			pitch += look_delta.y;
+			// due to Quat.to_euler, we seem to automatically get a [-180, 180] wrapping for yaw and roll, which is good enough
+			//yaw = wrap(yaw, f32::consts::TAU); // wrap into [0, 360deg] range
+			
+			// Snapped views may be outside the limits, only prevent going further out
+			pitch = pitch.clamp(pitch_min.min(prev_pitch), pitch_max.max(prev_pitch));
+			
+			// Leaving 6-DOF mode also levels the horizon this way
+			flycam.rotation_target = Quat::from_euler(euler, yaw, pitch, 0.0);
+		}
+		
+		//println!("Rot: {:8.3}, {:8.3}", yaw.to_degrees(), pitch.to_degrees());
 	}
-	//roll = (roll + f32::consts::PI) % f32::consts::TAU - f32::consts::PI; // wrap into [-180deg, 180deg] range
-	
-	transf.rotation = Quat::from_euler(euler, yaw, pitch, roll);
 	
-	//println!("Rot: {:8.3}, {:8.3}, {:8.3}", yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees());
+	if flycam.smooth_rotation {
+		// slerp takes the shortest path, so yaw wrapping around is not a problem
+		transf.rotation.smooth_nudge(&flycam.rotation_target, flycam.rotation_smooth, time.delta_secs());
+	}
+	else {
+		transf.rotation = flycam.rotation_target;
+	}
+	flycam.rotation_applied = transf.rotation;
+}
+// Remove roll while keeping the view direction, works even when upside down
+fn level_horizon(rot: Quat) -> Quat {
+	let forward = rot * Vec3::NEG_Z;
+	Transform::IDENTITY.looking_to(forward, Vec3::Y).rotation
 }
 fn movement(
 		time: &Res<Time>,
-		keyboard: &Res<ButtonInput<KeyCode>>,
+		keyboard: &ButtonInput<KeyCode>,
 		transf: &mut Transform, flycam: &mut Flycam) {
 	
-	fn get_move3d(keyboard: &Res<ButtonInput<KeyCode>>) -> Vec3 {
+	fn get_move3d(keyboard: &ButtonInput<KeyCode>) -> Vec3 {
 		let mut dir_local = Vec3::ZERO;
 		
 		if keyboard.pressed(KeyCode::KeyA) { dir_local.x -= 1.0; }
@@ -222,114 +613,174 @@ fn movement(
 		
 		dir_local.normalize_or_zero()
 	}
-	let dir_local = get_move3d(&keyboard);
+	let dir_local = get_move3d(keyboard);
 	let mut move_speed = dir_local.length(); // could be analog with gamepad input for get_move3d()
 	
-	// no movement resets speed
-	if move_speed == 0.0 {
+	// no movement resets speed, but keep a sprint speed while still coasting faster than base_speed
+	// otherwise pressing a key again while coasting would suddenly slow down
+	if move_speed == 0.0 && (!flycam.smooth_movement || flycam.velocity.length() <= flycam.base_speed) {
 		flycam.speed = flycam.base_speed;
 	}
 	
 	if keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
 		move_speed *= flycam.fast_multiplier;
-
+		
 		flycam.speed += flycam.base_speed * flycam.speedup_factor * time.delta_secs();
 	}
-
+	
 	flycam.speed = flycam.speed.clamp(flycam.base_speed, flycam.max_speed);
 	
 	//
-	let delta_local = dir_local * (time.delta_secs() * flycam.speed);
+	let vel_local = dir_local * flycam.speed;
 	
 	// WASD move only horizontally, even if looking up/down
 	// QE move up/down
-	if flycam.move_planar {
+	// (Does not make sense with a rolled 6-DOF camera)
+	let planar = flycam.walk || (flycam.move_planar && !flycam.six_dof);
+	let mut target_vel = if planar {
 		let (yaw, _, _) = transf.rotation.to_euler(EulerRot::YXZ);
-		let move_2d = Quat::from_rotation_y(yaw) * Vec3::new(delta_local.x, 0.0, delta_local.z);
+		let move_2d = Quat::from_rotation_y(yaw) * Vec3::new(vel_local.x, 0.0, vel_local.z);
 		
-		transf.translation += move_2d;
-		transf.translation.y += delta_local.y;
+		move_2d + Vec3::new(0.0, vel_local.y, 0.0)
 	}
 	// Move forward while looking down will move downwards
 	else {
-		transf.translation += transf.rotation * delta_local;
+		transf.rotation * vel_local
+	};
+	
+	if flycam.walk {
+		// Vertical velocity comes from gravity and jumping instead of QE
+		target_vel.y = flycam.velocity.y;
+	}
+	
+	if flycam.smooth_movement {
+		// Exponential, so it works the same no matter if base_speed is tiny or huge
+		let rate = if dir_local != Vec3::ZERO { flycam.accel } else { flycam.decel };
+		flycam.velocity.smooth_nudge(&target_vel, rate, time.delta_secs());
+	}
+	else {
+		flycam.velocity = target_vel;
 	}
-}
-
-fn update_camera(
-		time: Res<Time>,
-		keyboard: Res<ButtonInput<KeyCode>>,
-		mouse: Res<ButtonInput<MouseButton>>,
-		mut mouse_motion: MessageReader<MouseMotion>,
-		mut mouse_wheel: MessageReader<MouseWheel>,
-		mut cursor_options: Single<&mut CursorOptions>,
-		mut query: Query<(&mut Transform, &mut Flycam, &Camera, &mut Projection), With<Camera3d>>) {
 	
-	let mut cursor_opt = cursor_options.into_inner();
+	if flycam.walk {
+		flycam.velocity.y -= flycam.gravity * time.delta_secs();
+		
+		if flycam.grounded && keyboard.just_pressed(JUMP_KEY) {
+			flycam.velocity.y = flycam.jump_speed;
+		}
+	}
 	
-	for (mut transf, mut flycam, cam, mut proj) in &mut query {
-		if cam.is_active { // disabling rendering also disables controls
-			zoom(&time, &keyboard, &mut mouse_wheel, &mut flycam, proj.as_mut());
-			mouselook(&time, &keyboard, &mouse, &mut mouse_motion, &cursor_opt, &mut transf, &mut flycam, &proj);
-			movement(&time, &keyboard, &mut transf, &mut flycam);
-			// NOTE: controlling multiple cameras does not work since MessageReaders eat input
+	transf.translation += flycam.velocity * time.delta_secs();
+	
+	if let Some(target) = flycam.frame_target {
+		if dir_local != Vec3::ZERO {
+			flycam.frame_target = None;
+		}
+		else {
+			transf.translation.smooth_nudge(&target, flycam.frame_smooth, time.delta_secs());
+			
+			if transf.translation.distance(target) < flycam.focus_dist * 0.001 {
+				transf.translation = target;
+				flycam.frame_target = None;
+			}
 		}
 	}
 }
 
-// TODO: move this somewhere else but make it possible for other systems to share control of the cursor in some way
-// Ex: In-game Translation gizmos might want to change cursor icon, but not lock it, etc.
-// Maybe other users of cursor might want to know about mouselook and freeze during it etc.
-// Cameras then could ask cursor for mouselook state (ie. MOUSELOOK_BTN moves to cursor system)
-
-// Make sure to add and remove CursorIcon from Window when needed
-// both writing SystemCursorIcon::Default to it every frame and writing SystemCursorIcon::Default to it only to reset
-// both cause flickering when resizing window (bug in bevy?)
-fn update_cursor(
-		keyboard: Res<ButtonInput<KeyCode>>,
-		mouse: Res<ButtonInput<MouseButton>>,
-		window: Single<(Entity, &Window)>,
-		mut cursor_options: Single<&mut CursorOptions>,
-		mut commands: Commands) {
-	
-	let (window_e, window) = *window;
-	
-	// Handle alt-tab gracefully by fulling resetting everything
-	if !window.focused {
-		//println!("No Window Focus!");
-		cursor_options.visible = true;
-		cursor_options.grab_mode = CursorGrabMode::None;
-		commands.entity(window_e).remove::<CursorIcon>();
+// Push the camera out of overlapping bounding boxes along the axis of least penetration
+// Only approximate (no sweeping), so very fast movement can tunnel through thin objects
+fn collide(
+		transf: &mut Transform, flycam: &mut Flycam,
+		colliders: &Query<(&Aabb, &GlobalTransform), (With<Mesh3d>, Without<Flycam>, Without<Particle>, Without<ParticleBuffers>, Without<DebugRenderCopy>)>) {
+	flycam.grounded = false;
+	if !flycam.collision && !flycam.walk {
 		return;
 	}
 	
-	// Toggle mouse cursor visible via F2 (invisible cursor = FPS style mouselook)
-	if keyboard.just_pressed(KeyCode::F2) {
-		//println!("Toggle Cursor Visible");
-		cursor_options.visible = !cursor_options.visible;
-	}
-	
-	// Mouselooking using held MOUSELOOK_BTN or when cursor invisible
-	let mouselook = !cursor_options.visible || mouse.pressed(MOUSELOOK_BTN);
-	// Detect mouselook using cursor_options.grab_mode
-	let was_mouselook = cursor_options.grab_mode != CursorGrabMode::None;
-	
-	// Only update grab_mode and cursor_icon when needed instead of overwriting every frame to avoid glitches
-	// TODO: 
-	if mouselook != was_mouselook {
-		//println!("Toggle Mouselook");
-		if mouselook {
-			//println!("Mouselook=1");
-			// Lock mouse cursor, which means freeze in place, and change icon to a "We are mouselooking/dragging" style icon
-			// This feels more professional and clean than making it invisible or only constraining it to the window
-			cursor_options.grab_mode = CursorGrabMode::Locked;
-			let icon : CursorIcon = SystemCursorIcon::AllScroll.into();
-			commands.entity(window_e).insert(icon);
+	let r = flycam.collision_radius;
+	// Walking: box from the feet up to just above the eyes
+	let below = if flycam.walk { flycam.eye_height } else { r };
+	
+	// A few iterations to resolve corners between multiple boxes
+	for _ in 0..3 {
+		let mut any = false;
+		for (aabb, aabb_transf) in colliders {
+			let (bmin, bmax) = selection::aabb_to_world(aabb, aabb_transf);
+			let pmin = transf.translation - Vec3::new(r, below, r);
+			let pmax = transf.translation + Vec3::splat(r);
+			
+			if pmin.cmpge(bmax).any() || pmax.cmple(bmin).any() {
+				continue;
+			}
+			
+			// Smallest push out of the box
+			let push_neg = bmin - pmax; // negative values
+			let push_pos = bmax - pmin;
+			let mut push = Vec3::ZERO;
+			let mut best = f32::INFINITY;
+			for i in 0..3 {
+				for p in [push_neg[i], push_pos[i]] {
+					if p.abs() < best {
+						best = p.abs();
+						push = Vec3::ZERO;
+						push[i] = p;
+					}
+				}
+			}
+			
+			transf.translation += push;
+			// Stop moving into the surface
+			let normal = push.normalize_or_zero();
+			let into = flycam.velocity.dot(normal);
+			if into < 0.0 {
+				flycam.velocity -= normal * into;
+			}
+			if normal.y > 0.5 {
+				flycam.grounded = true;
+			}
+			any = true;
 		}
-		else {
-			//println!("Mouselook=0");
-			cursor_options.grab_mode = CursorGrabMode::None;
-			commands.entity(window_e).remove::<CursorIcon>();
+		if !any { break; }
+	}
+}
+
+fn update_camera(
+		time: Res<Time>,
+		keyboard: Res<ButtonInput<KeyCode>>,
+		input: Res<FlycamInput>,
+		control: Res<ControlledFlycam>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		primary_window: Query<Entity, With<PrimaryWindow>>,
+		colliders: Query<(&Aabb, &GlobalTransform), (With<Mesh3d>, Without<Flycam>, Without<Particle>, Without<ParticleBuffers>, Without<DebugRenderCopy>)>,
+		mut query: Query<(Entity, &mut Transform, &mut Flycam, &Camera, &mut Projection), With<Camera3d>>) {
+	
+	// Typing into egui, act as if no keys are pressed so smoothing etc. still continues
+	let no_keys = ButtonInput::<KeyCode>::default();
+	let no_input = FlycamInput::default();
+	let keyboard = if cursor::keyboard_captured_by_ui(&cursor, &egui_wants) { &no_keys } else { &*keyboard };
+	
+	for (entity, mut transf, mut flycam, cam, mut proj) in &mut query {
+		if flycam.orthographic != matches!(*proj, Projection::Orthographic(_)) {
+			apply_projection(&flycam, proj.as_mut());
 		}
+		
+		// disabling rendering also disables controls
+		if !cam.is_active { continue; }
+		
+		// Keyboard and mouse belong to the focused window, so only its controlled camera gets input
+		// other cameras still run with no input, so smoothing and inertia finish instead of freezing
+		let window = match cam.target.normalize(primary_window.single().ok()) {
+			Some(NormalizedRenderTarget::Window(window_ref)) => Some(window_ref.entity()),
+			_ => None,
+		};
+		let controlled = control.current == Some(entity) && window.is_some() && window == cursor.focused_window();
+		let (keyboard, input) = if controlled { (keyboard, &*input) } else { (&no_keys, &no_input) };
+		
+		zoom(&time, keyboard, input, &mut flycam, proj.as_mut());
+		view_controls(keyboard, &mut transf, &mut flycam, proj.as_mut());
+		mouselook(&time, keyboard, input, controlled && cursor.is_mouselook(), &mut transf, &mut flycam, &proj);
+		movement(&time, keyboard, &mut transf, &mut flycam);
+		collide(&mut transf, &mut flycam, &colliders);
 	}
 }
@@ -0,0 +1,256 @@
+use bevy::prelude::*;
+use bevy::ecs::message::MessageUpdateSystems;
+use bevy::input::{ ButtonState, keyboard::{ KeyboardInput, Key, NativeKey }, mouse::{ MouseButtonInput, MouseMotion, MouseWheel, MouseScrollUnit } };
+use bevy::reflect::{ DynamicEnum, Enum };
+use bevy::time::{ TimeSystems, TimeUpdateStrategy };
+use bevy::input::keyboard::KeyboardFocusLost;
+use bevy::window::{ CursorEntered, CursorLeft, CursorMoved, Ime, PrimaryWindow, WindowFocused };
+use serde_json::{ json, Value };
+use std::collections::VecDeque;
+use std::time::Duration;
+use crate::settings_file;
+
+// Record input messages of a whole session and replay them deterministically
+//   --record <file>  records from startup until exit (or F9)
+//   --replay <file>  replays, real input is ignored until the recording ends
+// Replaying from startup with the recorded frame times and settings reproduces the session exactly,
+// since the rngs in startup and ParticleSystem are seeded with constants
+// NOTE: text input (egui text fields) and other window events are not recorded
+pub struct InputReplayPlugin;
+impl Plugin for InputReplayPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(InputReplay::from_args())
+			.add_systems(PostStartup, begin)
+			// After messages are swapped, but before anything reads them
+			.add_systems(First, replay_frame.after(MessageUpdateSystems).before(TimeSystems))
+			.add_systems(Last, (record_frame, save_recording.after(record_frame)));
+	}
+}
+
+const STOP_RECORDING_KEY : KeyCode = KeyCode::F9;
+
+#[derive(Resource)]
+pub enum InputReplay {
+	Off,
+	// window: cursor position and focus at the start
+	Recording { path: String, settings: Value, window: Value, frames: Vec<Value> },
+	// cursor and focused are the replayed window state, real_focused is restored when the replay ends
+	Replaying { settings: Value, frames: VecDeque<Value>, cursor: Option<Vec2>, focused: bool, real_focused: bool },
+}
+impl InputReplay {
+	fn from_args() -> Self {
+		let args: Vec<String> = std::env::args().collect();
+		let arg = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).cloned();
+		
+		if let Some(path) = arg("--replay") {
+			match std::fs::read_to_string(&path).ok().and_then(|s| serde_json::from_str::<Value>(&s).ok()) {
+				Some(mut json) => {
+					let frames = match json["frames"].take() {
+						Value::Array(frames) => frames.into(),
+						_ => VecDeque::new(),
+					};
+					info!("Replaying {} frames from {path}", frames.len());
+					return InputReplay::Replaying {
+						settings: json["settings"].take(),
+						frames,
+						cursor: vec2_from_json(&json["window"]["cursor"]),
+						focused: json["window"]["focused"].as_bool().unwrap_or(true),
+						real_focused: true,
+					};
+				}
+				None => warn!("Failed to load input recording {path}!"),
+			}
+		}
+		else if let Some(path) = arg("--record") {
+			info!("Recording input to {path}");
+			return InputReplay::Recording { path, settings: Value::Null, window: Value::Null, frames: Vec::new() };
+		}
+		InputReplay::Off
+	}
+}
+
+// Enums like KeyCode are stored by variant name via reflection, avoids needing serde support in bevy
+// Only unit variants are supported, others (ex. KeyCode::Unidentified) are skipped
+fn enum_to_json(value: &dyn Enum) -> Option<Value> {
+	(value.field_len() == 0).then(|| Value::String(value.variant_name().into()))
+}
+fn enum_from_json<T: FromReflect>(json: &Value) -> Option<T> {
+	T::from_reflect(&DynamicEnum::new(json.as_str()?, ()))
+}
+fn vec2_to_json(v: Vec2) -> Value {
+	json!([v.x, v.y])
+}
+fn vec2_from_json(json: &Value) -> Option<Vec2> {
+	Some(Vec2::new(json[0].as_f64()? as f32, json[1].as_f64()? as f32))
+}
+
+// Record the settings, or restore the recorded ones, after settings.json was loaded in Startup
+fn begin(world: &mut World) {
+	let Some(mut replay) = world.remove_resource::<InputReplay>() else { return };
+	let window = world.query_filtered::<&Window, With<PrimaryWindow>>().single(world).ok()
+		.map(|w| (w.cursor_position(), w.focused));
+	match &mut replay {
+		InputReplay::Recording { settings, window: window_json, .. } => {
+			*settings = settings_file::serialize_all(world);
+			if let Some((cursor, focused)) = window {
+				*window_json = json!({ "cursor": cursor.map(vec2_to_json), "focused": focused });
+			}
+		}
+		InputReplay::Replaying { settings, real_focused, .. } => {
+			settings_file::deserialize_all(world, settings.take());
+			if let Some((_, focused)) = window {
+				*real_focused = focused;
+			}
+		}
+		InputReplay::Off => {}
+	}
+	world.insert_resource(replay);
+}
+
+fn record_frame(
+		time: Res<Time<Real>>,
+		mut keys: MessageReader<KeyboardInput>,
+		mut buttons: MessageReader<MouseButtonInput>,
+		mut motion: MessageReader<MouseMotion>,
+		mut wheel: MessageReader<MouseWheel>,
+		mut cursor_moved: MessageReader<CursorMoved>,
+		mut focus: MessageReader<WindowFocused>,
+		mut replay: ResMut<InputReplay>) {
+	let InputReplay::Recording { frames, .. } = replay.as_mut() else { return };
+	
+	let state = |s: ButtonState| s == ButtonState::Pressed;
+	
+	// Repeats don't change ButtonInput
+	let keys: Vec<Value> = keys.read().filter(|e| !e.repeat)
+		.filter_map(|e| Some(json!([enum_to_json(&e.key_code)?, state(e.state)]))).collect();
+	let buttons: Vec<Value> = buttons.read()
+		.filter_map(|e| Some(json!([enum_to_json(&e.button)?, state(e.state)]))).collect();
+	let motion: Vec<Value> = motion.read().map(|e| vec2_to_json(e.delta)).collect();
+	let wheel: Vec<Value> = wheel.read()
+		.map(|e| json!([e.unit == MouseScrollUnit::Line, e.x, e.y])).collect();
+	let cursor: Vec<Value> = cursor_moved.read().map(|e| vec2_to_json(e.position)).collect();
+	let focus: Vec<Value> = focus.read().map(|e| Value::Bool(e.focused)).collect();
+	
+	let mut frame = json!({ "dt": time.delta().as_secs_f64() });
+	// Most frames have no input, keep the file small
+	for (name, list) in [("keys", keys), ("buttons", buttons), ("motion", motion), ("wheel", wheel), ("cursor", cursor), ("focus", focus)] {
+		if !list.is_empty() {
+			frame[name] = Value::Array(list);
+		}
+	}
+	frames.push(frame);
+}
+
+fn save_recording(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		mut exit: MessageReader<AppExit>,
+		mut replay: ResMut<InputReplay>) {
+	let stop = keyboard.just_pressed(STOP_RECORDING_KEY);
+	if exit.read().count() == 0 && !stop {
+		return;
+	}
+	let InputReplay::Recording { path, settings, window, frames } = replay.as_mut() else { return };
+	
+	let json = json!({ "settings": settings, "window": window, "frames": frames });
+	match std::fs::write(&path, json.to_string()) {
+		Ok(_) => info!("Saved {} frames of input to {path}", frames.len()),
+		Err(_) => warn!("Failed to save input recording {path}!"),
+	}
+	
+	*replay = InputReplay::Off;
+}
+
+fn replay_frame(
+		mut replay: ResMut<InputReplay>,
+		mut strategy: ResMut<TimeUpdateStrategy>,
+		window: Single<(Entity, &mut Window), With<PrimaryWindow>>,
+		mut keys: ResMut<Messages<KeyboardInput>>,
+		mut buttons: ResMut<Messages<MouseButtonInput>>,
+		mut motion: ResMut<Messages<MouseMotion>>,
+		mut wheel: ResMut<Messages<MouseWheel>>,
+		mut cursor_moved: ResMut<Messages<CursorMoved>>,
+		mut focus: ResMut<Messages<WindowFocused>>,
+		mut cursor_entered: ResMut<Messages<CursorEntered>>,
+		mut cursor_left: ResMut<Messages<CursorLeft>>,
+		mut focus_lost: ResMut<Messages<KeyboardFocusLost>>,
+		mut ime: ResMut<Messages<Ime>>) {
+	let InputReplay::Replaying { frames, cursor, focused, real_focused, .. } = replay.as_mut() else { return };
+	let (window_e, mut window) = window.into_inner();
+	
+	// Keep track of real focus changes to restore it afterwards
+	for e in focus.drain() {
+		if e.window == window_e {
+			*real_focused = e.focused;
+		}
+	}
+	
+	let Some(frame) = frames.pop_front() else {
+		info!("Replay finished");
+		*strategy = TimeUpdateStrategy::Automatic;
+		window.bypass_change_detection().focused = *real_focused;
+		*replay = InputReplay::Off;
+		return;
+	};
+	
+	*strategy = TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(frame["dt"].as_f64().unwrap_or(0.0)));
+	
+	// Drop real input
+	keys.clear();
+	buttons.clear();
+	motion.clear();
+	wheel.clear();
+	cursor_moved.clear();
+	cursor_entered.clear();
+	cursor_left.clear();
+	focus_lost.clear();
+	ime.clear();
+	
+	let list = |name: &str| frame[name].as_array().cloned().unwrap_or_default();
+	let state = |pressed: &Value| if pressed.as_bool() == Some(true) { ButtonState::Pressed } else { ButtonState::Released };
+	
+	for e in list("keys") {
+		let Some(key_code) = enum_from_json::<KeyCode>(&e[0]) else { continue };
+		keys.write(KeyboardInput {
+			key_code,
+			logical_key: Key::Unidentified(NativeKey::Unidentified),
+			state: state(&e[1]),
+			text: None,
+			repeat: false,
+			window: window_e,
+		});
+	}
+	for e in list("buttons") {
+		let Some(button) = enum_from_json::<MouseButton>(&e[0]) else { continue };
+		buttons.write(MouseButtonInput { button, state: state(&e[1]), window: window_e });
+	}
+	for e in list("motion") {
+		let Some(delta) = vec2_from_json(&e) else { continue };
+		motion.write(MouseMotion { delta });
+	}
+	for e in list("wheel") {
+		let unit = if e[0].as_bool() == Some(true) { MouseScrollUnit::Line } else { MouseScrollUnit::Pixel };
+		wheel.write(MouseWheel {
+			unit,
+			x: e[1].as_f64().unwrap_or(0.0) as f32,
+			y: e[2].as_f64().unwrap_or(0.0) as f32,
+			window: window_e,
+		});
+	}
+	for e in list("cursor") {
+		let Some(position) = vec2_from_json(&e) else { continue };
+		*cursor = Some(position);
+		cursor_moved.write(CursorMoved { window: window_e, position, delta: None });
+	}
+	for e in list("focus") {
+		*focused = e.as_bool() == Some(true);
+		focus.write(WindowFocused { window: window_e, focused: *focused });
+	}
+	
+	// Selection and gizmos read the window cursor position and everything checks focus, not the messages
+	// winit writes the real ones into the window between frames, so override them every frame
+	// Bypassing change detection, otherwise bevy_winit would warp the OS cursor and focus the window to match
+	let window = window.bypass_change_detection();
+	window.set_cursor_position(*cursor);
+	window.focused = *focused;
+}
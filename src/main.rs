@@ -1,13 +1,25 @@
 #![allow(unused)]
+// serializer! expands into serde_json::json!, which recurses per field
+#![recursion_limit = "256"]
 
 mod phases;
 mod serialization;
 mod settings_file;
 mod egui_histogram;
 mod app_control;
+mod camera_hud;
+mod cursor;
 mod debug_camera;
+mod debug_render;
 mod flycam;
+mod input_replay;
+mod overlays;
+mod particle_bench;
+mod particle_render;
 mod particles;
+mod selection;
+mod skeleton_gizmos;
+mod transform_gizmo;
 
 use bevy::{
 	prelude::*,
@@ -96,9 +108,19 @@ fn main() {
 	));
 	app.add_plugins((
 		app_control::AppControlPlugin,
+		camera_hud::CameraHudPlugin,
+		cursor::CursorPlugin,
 		debug_camera::DebugCameraPlugin,
+		debug_render::DebugRenderPlugin,
 		flycam::FlycamPlugin,
+		input_replay::InputReplayPlugin,
+		overlays::OverlaysPlugin,
+		particle_bench::ParticleBenchPlugin,
+		particle_render::ParticleRenderPlugin,
 		particles::ParticlePlugin,
+		selection::SelectionPlugin,
+		skeleton_gizmos::SkeletonGizmosPlugin,
+		transform_gizmo::TransformGizmoPlugin,
 	));
 	
 	app.add_observer(do_very_specific_thing_to_object);
@@ -147,6 +169,8 @@ fn startup(
 			clear_color: ClearColorConfig::Custom(Color::NONE),
 			..default()
 		},
+		// Only render egui, otherwise gizmos and meshes get drawn a second time from this camera's origin
+		visibility::RenderLayers::none(),
 		Name::new("EguiCamera"),
 	));
 	
@@ -162,13 +186,7 @@ fn startup(
 			MeshMaterial3d(red.clone()), // just for debugging
 	));
 	commands.spawn((
-		DebugCamera,
-		Flycam::new( Transform::from_xyz(2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y) ),
-		Camera::default(),
-		bevy::render::view::Hdr,
-		bevy::core_pipeline::tonemapping::Tonemapping::TonyMcMapface,
-		bevy::post_process::bloom::Bloom::NATURAL,
-		Name::new("DebugFlycam"),
+		debug_camera::debug_camera("DebugFlycam".into(), Transform::from_xyz(2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y)),
 			Mesh3d(cube_mesh.clone()),
 			MeshMaterial3d(red.clone()), // just for debugging
 	));
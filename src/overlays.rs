@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use bevy::camera::primitives::Aabb;
+use bevy::gizmos::config::{ GizmoConfig, GizmoConfigGroup, GizmoLineConfig };
+use bevy::gizmos::aabb::AabbGizmoConfigGroup;
+use crate::phases::Phase;
+use crate::serialization::*;
+use crate::debug_render::DebugRenderCopy;
+use crate::flycam::{ Flycam, ControlledFlycam };
+use crate::particles::ParticleBuffers;
+
+// Spatial reference drawn with gizmos: adaptive ground grid, world origin axes and mesh bounding boxes
+pub struct OverlaysPlugin;
+impl Plugin for OverlaysPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(ReferenceOverlays::default())
+			// Slightly in front of the ground plane to avoid z-fighting
+			.insert_gizmo_config(GridGizmos, GizmoConfig {
+				line: GizmoLineConfig { width: 1.0, ..default() },
+				depth_bias: -0.0001,
+				..default()
+			})
+			.add_systems(Update, (draw_overlays, draw_aabbs).after(Phase::CameraUpdate));
+	}
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct GridGizmos;
+
+const GRID_COLOR : Color = Color::srgb(0.5, 0.5, 0.5);
+// Lines per level in each direction from the camera, the grid fades out towards the edge
+const GRID_LINES : i32 = 40;
+const AABB_COLOR : Color = Color::srgb(0.2, 0.8, 1.0);
+const PARTICLE_BOUNDS_COLOR : Color = Color::srgb(1.0, 0.5, 0.2);
+
+#[derive(Resource, Reflect, Clone)]
+#[reflect(Resource)]
+pub struct ReferenceOverlays {
+	pub grid: bool,
+	pub grid_height: f32, // world y of the grid
+	pub grid_alpha: f32,
+	pub axes: bool,
+	pub axes_size: f32,
+	// Bounding boxes of all meshes, including particles
+	pub aabbs: bool,
+}
+impl Default for ReferenceOverlays {
+	fn default() -> Self {
+		Self {
+			grid: true,
+			grid_height: 0.0,
+			grid_alpha: 0.5,
+			axes: true,
+			axes_size: 1.0,
+			aabbs: false,
+		}
+	}
+}
+serializer!(ReferenceOverlays, grid, grid_height, grid_alpha, axes, axes_size, aabbs);
+serializer_world!(ReferenceOverlays, Res<ReferenceOverlays>);
+
+fn draw_overlays(
+		overlays: Res<ReferenceOverlays>,
+		control: Res<ControlledFlycam>,
+		cameras: Query<&GlobalTransform, With<Flycam>>,
+		mut grid_gizmos: Gizmos<GridGizmos>,
+		mut gizmos: Gizmos) {
+	
+	if overlays.axes {
+		gizmos.axes(Transform::IDENTITY, overlays.axes_size);
+	}
+	
+	if overlays.grid {
+		// Adapt to the camera we are flying, so spacing and fade follow it
+		let Some(cam_transf) = control.current().and_then(|e| cameras.get(e).ok()) else { return };
+		draw_grid(&mut grid_gizmos, &overlays, cam_transf.translation());
+	}
+}
+
+// Instead of AabbGizmoConfigGroup::draw_all, which also draws hidden entities like pooled particles
+// Instanced particles get a box around all their particles, their Aabb only covers the single particle mesh
+fn draw_aabbs(
+		overlays: Res<ReferenceOverlays>,
+		meshes: Query<(&Aabb, &GlobalTransform, &ViewVisibility), (Without<ParticleBuffers>, Without<DebugRenderCopy>)>,
+		particle_buffers: Query<(&ParticleBuffers, &ViewVisibility)>,
+		mut gizmos: Gizmos<AabbGizmoConfigGroup>) {
+	if !overlays.aabbs { return; }
+	
+	for (aabb, transf, visibility) in &meshes {
+		if !visibility.get() { continue; }
+		
+		let local = Transform::from_translation(aabb.center.into()).with_scale((aabb.half_extents * 2.0).into());
+		gizmos.cuboid(transf.mul_transform(local), AABB_COLOR);
+	}
+	for (buffers, visibility) in &particle_buffers {
+		if !visibility.get() { continue; }
+		let Some((min, max)) = buffers.bounds() else { continue };
+		
+		gizmos.cuboid(Transform::from_translation((min + max) / 2.0).with_scale(max - min), PARTICLE_BOUNDS_COLOR);
+	}
+}
+
+// Two levels of grid lines, spacing in powers of 10 based on the camera height above the grid
+// the finer level fades out as the camera rises, until it becomes the coarser one, so there is no popping
+fn draw_grid(gizmos: &mut Gizmos<GridGizmos>, overlays: &ReferenceOverlays, cam_pos: Vec3) {
+	let height = (cam_pos.y - overlays.grid_height).abs().max(0.1);
+	let level = height.log10();
+	let base = 10.0_f32.powf(level.floor() - 1.0);
+	let blend = level - level.floor();
+	
+	for (spacing, alpha) in [(base, 1.0 - blend), (base * 10.0, 1.0)] {
+		let alpha = alpha * overlays.grid_alpha;
+		if alpha <= 0.01 { continue; }
+		
+		let radius = spacing * GRID_LINES as f32;
+		let center = (cam_pos.xz() / spacing).round() * spacing;
+		
+		for i in -GRID_LINES..=GRID_LINES {
+			let offset = i as f32 * spacing;
+			
+			for axis in [Vec2::X, Vec2::Y] {
+				let along = Vec2::new(axis.y, axis.x);
+				// Line position relative to the camera, lines further away start out fainter
+				let line = center + axis * offset;
+				let dist = (line - cam_pos.xz()).dot(axis).abs();
+				let a = alpha * (1.0 - dist / radius).max(0.0);
+				if a <= 0.0 { continue; }
+				
+				// Fade along the line from the point closest to the camera
+				let mid = line + along * (cam_pos.xz() - line).dot(along);
+				let to_3d = |p: Vec2| Vec3::new(p.x, overlays.grid_height, p.y);
+				let color = GRID_COLOR.with_alpha(a);
+				let edge = GRID_COLOR.with_alpha(0.0);
+				gizmos.line_gradient(to_3d(mid), to_3d(mid + along * radius), color, edge);
+				gizmos.line_gradient(to_3d(mid), to_3d(mid - along * radius), color, edge);
+			}
+		}
+	}
+}
@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+use crate::app_control::WindowSettings;
+use crate::particles::{ Particle, ParticleBackend, ParticleBudget, ParticleBuffers, ParticleEmitter };
+
+// Compare particle backends: run with --particle-bench [emitters]
+// spawns a grid of fast emitters with each backend in turn, measures frame times, logs the results and quits
+pub struct ParticleBenchPlugin;
+impl Plugin for ParticleBenchPlugin {
+	fn build(&self, app: &mut App) {
+		if let Some(bench) = ParticleBench::from_args() {
+			app
+				.insert_resource(bench)
+				.add_systems(Update, run_bench);
+		}
+	}
+}
+
+const DEFAULT_EMITTERS : u32 = 16;
+// Per emitter, about 2500 alive each with the default lifetimes
+const SPAWN_PERIOD : f32 = 0.0005;
+const WARMUP : f32 = 3.0;
+const MEASURE : f32 = 5.0;
+
+#[derive(Default)]
+struct BenchResult {
+	frames: u32,
+	time: f32,
+	particle_frames: u64, // sum of alive particles over all frames
+}
+
+enum BenchState {
+	Start,
+	// Emitters running, results only taken after WARMUP
+	Running { emitters: Vec<Entity>, elapsed: f32, result: BenchResult },
+	// Wait for the old particles to die before the next backend
+	Draining,
+}
+
+#[derive(Resource)]
+struct ParticleBench {
+	emitter_count: u32,
+	backends: Vec<ParticleBackend>,
+	results: Vec<(ParticleBackend, BenchResult)>,
+	state: BenchState,
+	// Settings to restore, vsync would cap frame times and the budget and lod limit the particle count
+	restore: Option<(bool, ParticleBudget)>,
+}
+impl ParticleBench {
+	fn from_args() -> Option<Self> {
+		let args: Vec<String> = std::env::args().collect();
+		let i = args.iter().position(|a| a == "--particle-bench")?;
+		let emitter_count = args.get(i + 1).and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_EMITTERS);
+		
+		info!("Particle benchmark with {emitter_count} emitters");
+		Some(Self {
+			emitter_count,
+			backends: vec![ParticleBackend::Entities, ParticleBackend::Instanced],
+			results: Vec::new(),
+			state: BenchState::Start,
+			restore: None,
+		})
+	}
+}
+
+fn run_bench(
+		time: Res<Time>,
+		mut bench: ResMut<ParticleBench>,
+		mut window_settings: ResMut<WindowSettings>,
+		mut budget: ResMut<ParticleBudget>,
+		entity_particles: Query<&Particle>,
+		buffers: Query<&ParticleBuffers>,
+		mut exit: MessageWriter<AppExit>,
+		mut commands: Commands) {
+	let bench = &mut *bench;
+	let dt = time.delta_secs();
+	// Pooled particles are Disabled, so they are not counted
+	let alive = entity_particles.iter().count() + buffers.iter().map(|b| b.len()).sum::<usize>();
+	
+	if bench.restore.is_none() {
+		bench.restore = Some((window_settings.vsync, budget.clone()));
+		window_settings.vsync = false;
+		budget.max_particles = u32::MAX;
+		budget.lod = false;
+	}
+	
+	match &mut bench.state {
+		BenchState::Start => {
+			let Some(&backend) = bench.backends.first() else { return };
+			
+			// Grid of emitters pointing up, particles fall back onto the ground plane
+			let side = (bench.emitter_count as f32).sqrt().ceil() as u32;
+			let emitters = (0..bench.emitter_count).map(|i| {
+				let pos = Vec3::new((i % side) as f32 - side as f32 / 2.0, 0.5, (i / side) as f32 - side as f32 / 2.0) * 2.0;
+				let mut emitter = ParticleEmitter::new(SPAWN_PERIOD);
+				emitter.backend = backend;
+				commands.spawn((
+					Transform::from_translation(pos).looking_to(Vec3::Y, Vec3::Z),
+					emitter,
+					Name::new("BenchEmitter"),
+				)).id()
+			}).collect();
+			
+			info!("Benchmarking {backend:?}...");
+			bench.state = BenchState::Running { emitters, elapsed: 0.0, result: BenchResult::default() };
+		}
+		BenchState::Running { emitters, elapsed, result } => {
+			*elapsed += dt;
+			if *elapsed > WARMUP {
+				result.frames += 1;
+				result.time += dt;
+				result.particle_frames += alive as u64;
+			}
+			if *elapsed > WARMUP + MEASURE {
+				for e in emitters.drain(..) {
+					commands.entity(e).despawn();
+				}
+				let backend = bench.backends.remove(0);
+				bench.results.push((backend, std::mem::take(result)));
+				bench.state = BenchState::Draining;
+			}
+		}
+		BenchState::Draining => {
+			if alive > 0 { return; }
+			
+			if !bench.backends.is_empty() {
+				bench.state = BenchState::Start;
+				return;
+			}
+			
+			info!("Particle benchmark results ({} emitters):", bench.emitter_count);
+			for (backend, r) in &bench.results {
+				let frames = r.frames.max(1) as f32;
+				let avg_particles = r.particle_frames as f32 / frames;
+				let frame_ms = r.time / frames * 1000.0;
+				// Particle updates (and draws) per second
+				let throughput = r.particle_frames as f32 / r.time.max(0.0001);
+				info!("  {backend:?}: {avg_particles:.0} particles, {frame_ms:.2} ms/frame, {:.1} fps, {:.2} M particles/s",
+					1000.0 / frame_ms, throughput / 1e6);
+			}
+			
+			if let Some((vsync, old_budget)) = bench.restore.take() {
+				window_settings.vsync = vsync;
+				*budget = old_budget;
+			}
+			exit.write(AppExit::Success);
+		}
+	}
+}
@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+use bevy::camera::visibility::RenderLayers;
+use bevy::core_pipeline::core_3d::Transparent3d;
+use bevy::ecs::query::QueryItem;
+use bevy::ecs::system::{ lifetimeless::*, SystemParamItem };
+use bevy::mesh::{ MeshVertexBufferLayoutRef, VertexBufferLayout };
+use bevy::pbr::{ MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup, SetMeshViewBindingArrayBindGroup };
+use bevy::render::{
+	extract_component::{ ExtractComponent, ExtractComponentPlugin },
+	mesh::{ allocator::MeshAllocator, RenderMesh, RenderMeshBufferInfo },
+	render_asset::RenderAssets,
+	render_phase::{ AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
+		RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewSortedRenderPhases },
+	render_resource::*,
+	renderer::{ RenderDevice, RenderQueue },
+	view::{ ExtractedView, NoIndirectDrawing, RenderVisibleEntities },
+	Render, RenderApp, RenderStartup, RenderSystems,
+};
+use bytemuck::{ Pod, Zeroable };
+
+// Draws all particles of an instanced emitter with a single instanced draw of the particle mesh
+// Based on bevy's custom_shader_instancing example, the instance buffer is rewritten every frame
+// NOTE: Uses draw_indexed instead of indirect draws, so cameras that can see instanced particles get NoIndirectDrawing,
+// which makes bevy draw all meshes of those cameras without gpu-driven batching, cameras without them are unaffected
+pub struct ParticleRenderPlugin;
+impl Plugin for ParticleRenderPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.add_plugins(ExtractComponentPlugin::<ParticleInstances>::default())
+			.add_systems(PostUpdate, update_indirect_drawing);
+		app.sub_app_mut(RenderApp)
+			.add_render_command::<Transparent3d, DrawParticles>()
+			.init_resource::<SpecializedMeshPipelines<ParticlePipeline>>()
+			.add_systems(RenderStartup, init_pipeline)
+			.add_systems(Render, (
+				queue_particles.in_set(RenderSystems::QueueMeshes),
+				prepare_instance_buffers.in_set(RenderSystems::PrepareResources),
+			));
+	}
+}
+
+const SHADER_PATH : &str = "shaders/particles.wgsl";
+
+// World space, the shader ignores the transform of the entity
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct ParticleInstance {
+	pub position: Vec3,
+	pub scale: f32,
+	pub color: [f32; 4],
+}
+
+// Instances to draw with the Mesh3d of the same entity, needs NoFrustumCulling since the mesh Aabb does not cover the particles
+#[derive(Component, Default, Clone)]
+pub struct ParticleInstances(pub Vec<ParticleInstance>);
+
+impl ExtractComponent for ParticleInstances {
+	type QueryData = &'static ParticleInstances;
+	type QueryFilter = ();
+	type Out = Self;
+	
+	fn extract_component(item: QueryItem<'_, '_, Self::QueryData>) -> Option<Self> {
+		Some(item.clone())
+	}
+}
+
+// Re-evaluated every frame, bevy extracts NoIndirectDrawing per frame too
+fn update_indirect_drawing(
+		particles: Query<Option<&RenderLayers>, With<ParticleInstances>>,
+		cameras: Query<(Entity, Option<&RenderLayers>, Has<NoIndirectDrawing>), With<Camera3d>>,
+		mut commands: Commands) {
+	let default_layers = RenderLayers::default();
+	
+	for (e, cam_layers, has_no_indirect) in &cameras {
+		let cam_layers = cam_layers.unwrap_or(&default_layers);
+		let needed = particles.iter().any(|layers| layers.unwrap_or(&default_layers).intersects(cam_layers));
+		
+		if needed && !has_no_indirect {
+			commands.entity(e).insert(NoIndirectDrawing);
+		}
+		else if !needed && has_no_indirect {
+			commands.entity(e).remove::<NoIndirectDrawing>();
+		}
+	}
+}
+
+fn queue_particles(
+		draw_functions: Res<DrawFunctions<Transparent3d>>,
+		pipeline: Res<ParticlePipeline>,
+		mut pipelines: ResMut<SpecializedMeshPipelines<ParticlePipeline>>,
+		pipeline_cache: Res<PipelineCache>,
+		meshes: Res<RenderAssets<RenderMesh>>,
+		render_mesh_instances: Res<RenderMeshInstances>,
+		particle_meshes: Query<&ParticleInstances>,
+		mut phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
+		views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa)>) {
+	let draw_particles = draw_functions.read().id::<DrawParticles>();
+	
+	for (view, visible_entities, msaa) in &views {
+		let Some(phase) = phases.get_mut(&view.retained_view_entity) else { continue };
+		
+		// Particles fade out, so always blend
+		let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples()) | MeshPipelineKey::from_hdr(view.hdr) | MeshPipelineKey::BLEND_ALPHA;
+		let rangefinder = view.rangefinder3d();
+		
+		// Visible entities already respect RenderLayers and visibility, particle meshes have NoFrustumCulling
+		for &(entity, main_entity) in visible_entities.iter::<Mesh3d>() {
+			let Ok(instances) = particle_meshes.get(entity) else { continue };
+			if instances.0.is_empty() { continue; }
+			let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(main_entity) else { continue };
+			let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else { continue };
+			
+			let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
+			let Ok(pipeline) = pipelines.specialize(&pipeline_cache, &pipeline, key, &mesh.layout) else { continue };
+			
+			// Sorted as a whole by the emitter position, individual particles are not sorted
+			phase.add(Transparent3d {
+				entity: (entity, main_entity),
+				pipeline,
+				draw_function: draw_particles,
+				distance: rangefinder.distance_translation(&mesh_instance.translation),
+				batch_range: 0..1,
+				extra_index: PhaseItemExtraIndex::None,
+				indexed: true,
+			});
+		}
+	}
+}
+
+#[derive(Component)]
+struct InstanceBuffer {
+	buffer: Buffer,
+	capacity: usize, // in instances
+	length: usize,
+}
+
+fn prepare_instance_buffers(
+		mut query: Query<(Entity, &ParticleInstances, Option<&mut InstanceBuffer>)>,
+		render_device: Res<RenderDevice>,
+		render_queue: Res<RenderQueue>,
+		mut commands: Commands) {
+	for (entity, instances, buffer) in &mut query {
+		let data: &[u8] = bytemuck::cast_slice(instances.0.as_slice());
+		
+		// Render world entities are retained, so the buffer is reused and only grows
+		if let Some(mut buffer) = buffer && buffer.capacity >= instances.0.len() {
+			if !data.is_empty() {
+				render_queue.write_buffer(&buffer.buffer, 0, data);
+			}
+			buffer.length = instances.0.len();
+			continue;
+		}
+		if instances.0.is_empty() { continue; }
+		
+		// Room to grow, so a slowly rising particle count doesn't reallocate every frame
+		let capacity = instances.0.len().next_power_of_two();
+		let buffer = render_device.create_buffer(&BufferDescriptor {
+			label: Some("particle instance buffer"),
+			size: (capacity * size_of::<ParticleInstance>()) as u64,
+			usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		render_queue.write_buffer(&buffer, 0, data);
+		commands.entity(entity).insert(InstanceBuffer { buffer, capacity, length: instances.0.len() });
+	}
+}
+
+#[derive(Resource)]
+struct ParticlePipeline {
+	shader: Handle<Shader>,
+	mesh_pipeline: MeshPipeline,
+}
+
+fn init_pipeline(
+		asset_server: Res<AssetServer>,
+		mesh_pipeline: Res<MeshPipeline>,
+		mut commands: Commands) {
+	commands.insert_resource(ParticlePipeline {
+		shader: asset_server.load(SHADER_PATH),
+		mesh_pipeline: mesh_pipeline.clone(),
+	});
+}
+
+impl SpecializedMeshPipeline for ParticlePipeline {
+	type Key = MeshPipelineKey;
+	
+	fn specialize(&self, key: Self::Key, layout: &MeshVertexBufferLayoutRef) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+		let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+		
+		descriptor.vertex.shader = self.shader.clone();
+		descriptor.vertex.buffers.push(VertexBufferLayout {
+			array_stride: size_of::<ParticleInstance>() as u64,
+			step_mode: VertexStepMode::Instance,
+			attributes: vec![
+				// Locations 0-2 are the mesh position, normal and uv
+				VertexAttribute {
+					format: VertexFormat::Float32x4,
+					offset: 0,
+					shader_location: 3,
+				},
+				VertexAttribute {
+					format: VertexFormat::Float32x4,
+					offset: VertexFormat::Float32x4.size(),
+					shader_location: 4,
+				},
+			],
+		});
+		if let Some(fragment) = &mut descriptor.fragment {
+			fragment.shader = self.shader.clone();
+		}
+		Ok(descriptor)
+	}
+}
+
+type DrawParticles = (
+	SetItemPipeline,
+	SetMeshViewBindGroup<0>,
+	SetMeshViewBindingArrayBindGroup<1>,
+	SetMeshBindGroup<2>,
+	DrawMeshInstanced,
+);
+
+struct DrawMeshInstanced;
+impl<P: PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+	type Param = (
+		SRes<RenderAssets<RenderMesh>>,
+		SRes<RenderMeshInstances>,
+		SRes<MeshAllocator>,
+	);
+	type ViewQuery = ();
+	type ItemQuery = Read<InstanceBuffer>;
+	
+	#[inline]
+	fn render<'w>(
+			item: &P,
+			_view: (),
+			instance_buffer: Option<&'w InstanceBuffer>,
+			(meshes, render_mesh_instances, mesh_allocator): SystemParamItem<'w, '_, Self::Param>,
+			pass: &mut TrackedRenderPass<'w>) -> RenderCommandResult {
+		let mesh_allocator = mesh_allocator.into_inner();
+		
+		let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(item.main_entity()) else { return RenderCommandResult::Skip };
+		let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else { return RenderCommandResult::Skip };
+		let Some(instance_buffer) = instance_buffer else { return RenderCommandResult::Skip };
+		let Some(vertex_slice) = mesh_allocator.mesh_vertex_slice(&mesh_instance.mesh_asset_id) else { return RenderCommandResult::Skip };
+		
+		pass.set_vertex_buffer(0, vertex_slice.buffer.slice(..));
+		pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+		let instances = 0..instance_buffer.length as u32;
+		
+		match &gpu_mesh.buffer_info {
+			RenderMeshBufferInfo::Indexed { index_format, count } => {
+				let Some(index_slice) = mesh_allocator.mesh_index_slice(&mesh_instance.mesh_asset_id) else { return RenderCommandResult::Skip };
+				
+				pass.set_index_buffer(index_slice.buffer.slice(..), 0, *index_format);
+				pass.draw_indexed(index_slice.range.start..(index_slice.range.start + count), vertex_slice.range.start as i32, instances);
+			}
+			RenderMeshBufferInfo::NonIndexed => {
+				pass.draw(vertex_slice.range, instances);
+			}
+		}
+		RenderCommandResult::Success
+	}
+}
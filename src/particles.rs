@@ -1,102 +1,684 @@
-use std::f32::INFINITY;
 use bevy::prelude::*;
+use bevy::camera::visibility::NoFrustumCulling;
+use bevy::ecs::entity_disabling::Disabled;
+use bevy::ecs::query::Allow;
+use bevy::math::StableInterpolate;
+use bevy::platform::collections::HashMap;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use crate::app_control::WindowSettings;
-use crate::flycam::Flycam;
+use crate::serialization::*;
+use crate::debug_camera::MainCamera;
+use crate::flycam::ControlledFlycam;
+use crate::particle_render::{ ParticleInstance, ParticleInstances };
 
 pub struct ParticlePlugin;
 
 impl Plugin for ParticlePlugin {
 	fn build(&self, app: &mut App) {
 		app
+			.insert_resource(ParticleBudget::default())
 			.add_systems(Startup, setup_data)
-			.add_systems(Update, spawn_particles)
-			.add_systems(Update, update_particles.after(spawn_particles));
+			.add_systems(Update, (
+				simulate_instanced.before(spawn_particles),
+				spawn_particles,
+				update_particles.after(spawn_particles),
+				write_instances.after(spawn_particles),
+			));
 	}
 }
 
 #[derive(Resource)]
 struct ParticleSystem {
 	mesh: Handle<Mesh>,
-	material: Handle<StandardMaterial>,
 	rng: ChaCha8Rng,
+	// Disabled particle entities to reuse instead of spawning and despawning all the time
+	// never shrinks, but the budget limits how many particles can exist at once
+	// Disabled hides them from all queries that don't explicitly ask for it, including rendering
+	pool: Vec<Entity>,
+}
+impl ParticleSystem {
+	fn release(&mut self, e: Entity, commands: &mut Commands) {
+		commands.entity(e).insert(Disabled);
+		self.pool.push(e);
+	}
 }
 fn setup_data (
 	mut commands: Commands,
 	mut meshes: ResMut<Assets<Mesh>>,
-	mut materials: ResMut<Assets<StandardMaterial>>
 ) {
 	let mesh = meshes.add(Cuboid::new(0.1, 0.1, 0.1));
-	let material = materials.add(Color::srgb_u8(60, 255, 70));
 	
 	let rng = ChaCha8Rng::seed_from_u64(19878367467711);
 	
-	commands.insert_resource(ParticleSystem{ mesh, material, rng });
+	commands.insert_resource(ParticleSystem{ mesh, rng, pool: Vec::new() });
 }
 
+// Piecewise linear curve over the normalized particle life [0, 1], keys sorted by time
+#[derive(Clone, Debug, Reflect)]
+pub struct OverLife<T> {
+	pub keys: Vec<(f32, T)>,
+}
+impl<T: StableInterpolate + Copy> OverLife<T> {
+	pub fn new(keys: impl Into<Vec<(f32, T)>>) -> Self {
+		Self { keys: keys.into() }
+	}
+	pub fn constant(value: T) -> Self {
+		Self::new([(0.0, value)])
+	}
+	pub fn sample(&self, t: f32) -> Option<T> {
+		let first = self.keys.first()?;
+		let last = self.keys.last()?;
+		// NaN fails both comparisons
+		if t <= first.0 || t.is_nan() { return Some(first.1); }
+		if t >= last.0 { return Some(last.1); }
+		
+		let i = self.keys.partition_point(|(k, _)| *k <= t);
+		// Only with unsorted keys (ex. edited in the inspector)
+		if i == 0 { return Some(first.1); }
+		let (t0, v0) = self.keys[i - 1];
+		let (t1, v1) = self.keys[i];
+		Some(v0.interpolate_stable(&v1, (t - t0) / (t1 - t0).max(0.00001)))
+	}
+}
 
-#[derive(Component)]
+// Colors over life are quantized into a palette of materials shared by all particles of an emitter
+// instead of a material per particle
+const PALETTE_SIZE : usize = 16;
+
+
+// Where particles spawn and which way they go, in the emitter's local space (+Z is forward for the particles)
+#[derive(Clone, Debug, Reflect)]
+pub enum EmitterShape {
+	Point, // all directions
+	Sphere { radius: f32 }, // outwards from the surface
+	Hemisphere { radius: f32 }, // outwards from the +Z half
+	Cone { angle: f32, radius: f32 }, // half angle in degrees around +Z, from a disc of radius
+	Box { half_extents: Vec3 }, // towards +Z from anywhere inside
+	MeshSurface { mesh: Handle<Mesh> }, // along the normal of a random point on the mesh surface
+}
+
+#[derive(Reflect, Clone, Copy, PartialEq, Debug)]
+pub enum ParticleBackend {
+	// Entity per particle, simple but does not scale past a few thousand
+	Entities,
+	// SoA buffers per emitter, drawn with a single instanced draw by particle_render
+	// NOTE: Not on par with Entities: fixed fake lighting without shadows, no rotation, unsorted within the emitter
+	// and debug render modes don't apply
+	Instanced,
+}
+
+// What to do with new particles when a max_particles limit is reached
+#[derive(Reflect, Clone, Copy, PartialEq, Debug)]
+pub enum OverflowPolicy {
+	DropNew,
+	// Make room by killing the oldest particles of the emitter that wants to spawn
+	KillOldest,
+}
+serializer_enum!(OverflowPolicy, DropNew, KillOldest);
+
+// Limits for all emitters together
+#[derive(Resource, Reflect, Clone)]
+#[reflect(Resource)]
+pub struct ParticleBudget {
+	pub max_particles: u32,
+	// NOTE: KillOldest kills from the spawning emitter, not the globally oldest particles
+	pub overflow: OverflowPolicy,
+	// Emission rate falls off linearly from full at lod_near to nothing at lod_far from the active camera
+	pub lod: bool,
+	pub lod_near: f32,
+	pub lod_far: f32,
+}
+impl Default for ParticleBudget {
+	fn default() -> Self {
+		Self {
+			max_particles: 20000,
+			overflow: OverflowPolicy::DropNew,
+			lod: true,
+			lod_near: 30.0,
+			lod_far: 100.0,
+		}
+	}
+}
+serializer!(ParticleBudget, max_particles, overflow, lod, lod_near, lod_far);
+serializer_world!(ParticleBudget, Res<ParticleBudget>);
+
+// How many of count new particles fit with alive existing ones and how many of the oldest (up to killable) to kill for them
+// returns (kill, spawn)
+fn fit_budget(policy: OverflowPolicy, max: usize, alive: usize, killable: usize, count: usize) -> (usize, usize) {
+	let free = max.saturating_sub(alive);
+	if count <= free {
+		return (0, count);
+	}
+	match policy {
+		OverflowPolicy::DropNew => (0, free),
+		OverflowPolicy::KillOldest => {
+			let kill = (count - free).min(killable);
+			(kill, free + kill)
+		}
+	}
+}
+
+#[derive(Clone, Debug, Reflect)]
+pub struct Burst {
+	pub time: f32, // seconds into the cycle
+	pub count: u32,
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
 pub struct ParticleEmitter {
+	// Continuous emission, 0 to only emit bursts
 	pub spawn_period: f32,
+	// Switching only affects new particles, existing ones live out their lifetime in the old backend
+	pub backend: ParticleBackend,
+	// In addition to the global ParticleBudget, unlimited by default
+	pub max_particles: u32,
+	pub overflow: OverflowPolicy,
+	#[reflect(ignore)]
 	time_since_last_spawn: f32,
+	// To interpolate spawn positions of particles emitted during the frame
+	#[reflect(ignore)]
+	prev_transform: Option<GlobalTransform>,
+	
+	pub shape: EmitterShape,
+	pub speed_min: f32,
+	pub speed_max: f32,
+	// Fraction of the emitter's own velocity added to particles
+	pub inherit_velocity: f32,
+	
+	pub bursts: Vec<Burst>,
+	// Length of one cycle, emission stops after it unless looping
+	pub duration: f32,
+	pub looping: bool,
+	#[reflect(ignore)]
+	cycle_time: f32,
+	
+	// Random lifetime in seconds in [min, max]
+	pub lifetime_min: f32,
+	pub lifetime_max: f32,
+	
+	pub scale_over_life: OverLife<f32>,
+	pub color_over_life: OverLife<LinearRgba>,
+	// Exponential speed damping rate (1/s)
+	pub drag_over_life: OverLife<f32>,
+	
+	#[reflect(ignore)]
+	palette: Vec<Handle<StandardMaterial>>,
+	#[reflect(ignore)]
+	palette_colors: Vec<(f32, LinearRgba)>, // color_over_life the palette was built from
+	
+	// Triangles and cumulative areas of the MeshSurface mesh
+	#[reflect(ignore)]
+	mesh_triangles: Option<(AssetId<Mesh>, Vec<(Triangle3d, f32)>)>,
+	
+	// Child entity with the ParticleBuffers for the instanced backend
+	#[reflect(ignore)]
+	buffers_entity: Option<Entity>,
 }
 impl ParticleEmitter {
-	pub fn new(spawn_period: f32) -> Self { Self{
-		spawn_period,
-		time_since_last_spawn: INFINITY
-	} }
+	pub fn new(spawn_period: f32) -> Self {
+		let color = LinearRgba::from(Color::srgb_u8(60, 255, 70));
+		Self{
+			spawn_period,
+			backend: ParticleBackend::Entities,
+			max_particles: u32::MAX,
+			overflow: OverflowPolicy::DropNew,
+			// Emit first particle immediately
+			time_since_last_spawn: spawn_period,
+			prev_transform: None,
+			
+			shape: EmitterShape::Cone { angle: 8.0, radius: 0.0 },
+			speed_min: 6.0,
+			speed_max: 8.0,
+			inherit_velocity: 0.0,
+			
+			bursts: Vec::new(),
+			duration: 1.0,
+			looping: true,
+			cycle_time: 0.0,
+			
+			lifetime_min: 1.0,
+			lifetime_max: 1.5,
+			// Shrink and fade out at the end instead of popping
+			scale_over_life: OverLife::new([(0.0, 1.0), (0.7, 1.0), (1.0, 0.2)]),
+			color_over_life: OverLife::new([(0.0, color), (0.6, color), (1.0, color.with_alpha(0.0))]),
+			drag_over_life: OverLife::constant(0.0),
+			
+			palette: Vec::new(),
+			palette_colors: Vec::new(),
+			mesh_triangles: None,
+			buffers_entity: None,
+		}
+	}
+	
+	fn palette_index(t: f32) -> usize {
+		(t.clamp(0.0, 1.0) * (PALETTE_SIZE - 1) as f32).round() as usize
+	}
+	
+	// Rebuild if color_over_life was edited
+	fn update_palette(&mut self, materials: &mut Assets<StandardMaterial>) {
+		if !self.palette.is_empty() && self.palette_colors == self.color_over_life.keys {
+			return;
+		}
+		self.palette_colors = self.color_over_life.keys.clone();
+		
+		let colors: Vec<LinearRgba> = (0..PALETTE_SIZE)
+			.map(|i| self.color_over_life.sample(i as f32 / (PALETTE_SIZE - 1) as f32).unwrap_or(LinearRgba::WHITE))
+			.collect();
+		// Only pay for blending if the particles actually fade
+		let alpha_mode = if colors.iter().any(|c| c.alpha < 1.0) { AlphaMode::Blend } else { AlphaMode::Opaque };
+		
+		self.palette = colors.into_iter().map(|c| materials.add(StandardMaterial {
+			base_color: c.into(),
+			alpha_mode,
+			..default()
+		})).collect();
+	}
 }
 
+const GRAVITY : Vec3 = Vec3::new(0.0, -10.0, 0.0);
+// Avoid spawning forever after a hitch or with a tiny spawn_period
+const MAX_SPAWNS_PER_FRAME : u32 = 1000;
+
 #[derive(Component)]
-struct Particle {
+pub struct Particle {
 	velocity: Vec3,
+	age: f32,
+	lifetime: f32,
+	// For the over-life curves, particles outlive their emitter with their last state
+	emitter: Entity,
+	palette_index: usize,
+}
+
+// Particles of an instanced emitter as structure of arrays, lives on a child of the emitter
+// so unlike the entity backend the particles disappear with the emitter
+#[derive(Component, Default)]
+pub struct ParticleBuffers {
+	positions: Vec<Vec3>,
+	velocities: Vec<Vec3>,
+	ages: Vec<f32>,
+	lifetimes: Vec<f32>,
+}
+impl ParticleBuffers {
+	pub fn len(&self) -> usize {
+		self.ages.len()
+	}
+	// World space (min, max) of the particle positions
+	pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+		let first = *self.positions.first()?;
+		Some(self.positions.iter().fold((first, first), |(min, max), p| (min.min(*p), max.max(*p))))
+	}
+	fn push(&mut self, position: Vec3, velocity: Vec3, age: f32, lifetime: f32) {
+		self.positions.push(position);
+		self.velocities.push(velocity);
+		self.ages.push(age);
+		// Ages are divided by it for the over-life curves
+		self.lifetimes.push(lifetime.max(0.0001));
+	}
+	fn swap_remove(&mut self, i: usize) {
+		self.positions.swap_remove(i);
+		self.velocities.swap_remove(i);
+		self.ages.swap_remove(i);
+		self.lifetimes.swap_remove(i);
+	}
+	fn kill_oldest(&mut self, count: usize) {
+		let mut order: Vec<usize> = (0..self.len()).collect();
+		order.sort_unstable_by(|a, b| self.ages[*b].total_cmp(&self.ages[*a]));
+		order.truncate(count);
+		// Descending, so swap_remove only moves particles we keep
+		order.sort_unstable_by(|a, b| b.cmp(a));
+		for i in order {
+			self.swap_remove(i);
+		}
+	}
+}
+
+fn random_range(rng: &mut ChaCha8Rng, min: f32, max: f32) -> f32 {
+	if max > min { rng.random_range(min..max) } else { min }
+}
+fn random_direction(rng: &mut ChaCha8Rng) -> Vec3 {
+	let z: f32 = rng.random_range(-1.0..=1.0);
+	let phi = rng.random_range(0.0..std::f32::consts::TAU);
+	let r = (1.0 - z * z).max(0.0).sqrt();
+	Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+fn random_in_disc(rng: &mut ChaCha8Rng, radius: f32) -> Vec2 {
+	let r = radius * rng.random_range(0.0_f32..=1.0).sqrt();
+	let phi = rng.random_range(0.0..std::f32::consts::TAU);
+	Vec2::new(r * phi.cos(), r * phi.sin())
+}
+
+impl ParticleEmitter {
+	// Local position and direction of a new particle
+	fn sample_shape(&mut self, rng: &mut ChaCha8Rng, meshes: &Assets<Mesh>) -> (Vec3, Vec3) {
+		match &self.shape {
+			EmitterShape::Point => (Vec3::ZERO, random_direction(rng)),
+			EmitterShape::Sphere { radius } => {
+				let dir = random_direction(rng);
+				(dir * *radius, dir)
+			}
+			EmitterShape::Hemisphere { radius } => {
+				let mut dir = random_direction(rng);
+				dir.z = dir.z.abs();
+				(dir * *radius, dir)
+			}
+			EmitterShape::Cone { angle, radius } => {
+				// Uniform over the spherical cap
+				let cos_max = angle.to_radians().cos();
+				let z = rng.random_range(cos_max.min(1.0)..=1.0);
+				let phi = rng.random_range(0.0..std::f32::consts::TAU);
+				let r = (1.0 - z * z).max(0.0).sqrt();
+				(random_in_disc(rng, *radius).extend(0.0), Vec3::new(r * phi.cos(), r * phi.sin(), z))
+			}
+			EmitterShape::Box { half_extents } => {
+				let h = *half_extents;
+				let pos = Vec3::new(random_range(rng, -h.x, h.x), random_range(rng, -h.y, h.y), random_range(rng, -h.z, h.z));
+				(pos, Vec3::Z)
+			}
+			EmitterShape::MeshSurface { mesh } => {
+				let id = mesh.id();
+				if self.mesh_triangles.as_ref().is_none_or(|(cached, _)| *cached != id) {
+					// Mesh might still be loading, try again next time
+					let Some(tris) = meshes.get(id).and_then(|m| m.triangles().ok()) else { return (Vec3::ZERO, Vec3::Z) };
+					let mut total = 0.0;
+					let tris = tris.map(|t| { total += t.area(); (t, total) }).collect();
+					self.mesh_triangles = Some((id, tris));
+				}
+				let Some((_, tris)) = &self.mesh_triangles else { return (Vec3::ZERO, Vec3::Z) };
+				let Some(&(_, total)) = tris.last() else { return (Vec3::ZERO, Vec3::Z) };
+				
+				// Pick a triangle weighted by area, then a uniform point on it
+				let x = rng.random_range(0.0..=total);
+				let (tri, _) = tris[tris.partition_point(|(_, sum)| *sum < x).min(tris.len() - 1)];
+				let (mut u, mut v): (f32, f32) = (rng.random_range(0.0..=1.0), rng.random_range(0.0..=1.0));
+				if u + v > 1.0 { (u, v) = (1.0 - u, 1.0 - v); }
+				let [a, b, c] = tri.vertices;
+				let pos = a + (b - a) * u + (c - a) * v;
+				let normal = tri.normal().map_or(Vec3::Z, |n| *n);
+				(pos, normal)
+			}
+		}
+	}
 }
 
 fn spawn_particles(
 		time: Res<Time>,
-		spawners: Query<(&mut ParticleEmitter, &GlobalTransform)>,
+		spawners: Query<(Entity, &mut ParticleEmitter, &GlobalTransform)>,
+		mut buffers: Query<&mut ParticleBuffers>,
+		// Including the pooled ones
+		mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut MeshMaterial3d<StandardMaterial>, Has<Disabled>), Allow<Disabled>>,
 		mut sys: ResMut<ParticleSystem>,
+		budget: Res<ParticleBudget>,
+		control: Res<ControlledFlycam>,
+		cameras: Query<&GlobalTransform, With<Camera>>,
+		main_cam: Query<&GlobalTransform, With<MainCamera>>,
+		mut materials: ResMut<Assets<StandardMaterial>>,
+		meshes: Res<Assets<Mesh>>,
+		// Ages (seconds ago during this frame) of all particles to spawn this frame, reused for all emitters
+		mut ages: Local<Vec<f32>>,
 		mut commands: Commands) {
-	let emit_speed: f32 = 7.0;
-	let speed_variation: f32 = 1.0;
+	let dt = time.delta_secs();
+	// LOD around the camera we are flying, which is only ever an active one
+	let cam_pos = control.current().and_then(|e| cameras.get(e).ok()).or(main_cam.single().ok()).map(|t| t.translation());
 	
-	for (mut spawner, transform) in spawners {
-		spawner.time_since_last_spawn += time.delta_secs();
-		if spawner.time_since_last_spawn >= spawner.spawn_period {
-			spawner.time_since_last_spawn -= spawner.spawn_period;
+	// Alive entity particles (entity, age) per emitter, to count them and find the oldest
+	let mut alive: HashMap<Entity, Vec<(Entity, f32)>> = HashMap::default();
+	for (e, particle, .., pooled) in &particles {
+		if !pooled {
+			alive.entry(particle.emitter).or_default().push((e, particle.age));
+		}
+	}
+	let mut total_alive = alive.values().map(Vec::len).sum::<usize>() + buffers.iter().map(|b| b.len()).sum::<usize>();
+	
+	for (emitter_e, mut spawner, transform) in spawners {
+		let prev = spawner.prev_transform.unwrap_or(*transform);
+		spawner.prev_transform = Some(*transform);
+		
+		let emitter_velocity = if dt > 0.0 { (transform.translation() - prev.translation()) / dt } else { Vec3::ZERO };
+		
+		spawner.update_palette(&mut materials);
+		
+		ages.clear();
+		
+		let cycle_start = spawner.cycle_time;
+		let cycle_end = cycle_start + dt;
+		let duration = spawner.duration.max(0.0001);
+		let active = spawner.looping || cycle_start < duration;
+		
+		// Emission LOD, distant emitters spawn fewer particles
+		let rate = match cam_pos {
+			Some(cam_pos) if budget.lod => {
+				let dist = transform.translation().distance(cam_pos);
+				1.0 - ((dist - budget.lod_near) / (budget.lod_far - budget.lod_near).max(0.001)).clamp(0.0, 1.0)
+			}
+			_ => 1.0,
+		};
+		
+		if active && spawner.spawn_period > 0.0 && rate > 0.0 {
+			spawner.time_since_last_spawn += dt * rate;
 			
-			let var = Vec3::new(sys.rng.random_range(-1.0..1.0),
-			                          sys.rng.random_range(-1.0..1.0),
-			                          sys.rng.random_range(-1.0..1.0));
+			while spawner.time_since_last_spawn >= spawner.spawn_period && ages.len() < MAX_SPAWNS_PER_FRAME as usize {
+				spawner.time_since_last_spawn -= spawner.spawn_period;
+				// How long ago during this frame the particle should have been spawned
+				ages.push((spawner.time_since_last_spawn / rate).min(dt));
+			}
 			
-			commands.spawn((
-				Mesh3d(sys.mesh.clone()),
-				MeshMaterial3d(sys.material.clone()),
-				Transform {
-					translation: transform.translation(),
-					rotation: transform.rotation(),
-					..Default::default()
+			// Drop the backlog we could not spawn
+			if ages.len() == MAX_SPAWNS_PER_FRAME as usize {
+				spawner.time_since_last_spawn = spawner.time_since_last_spawn.min(spawner.spawn_period);
+			}
+		}
+		
+		// Bursts whose time was crossed this frame, including ones in the next cycles when looping
+		if active && !spawner.bursts.is_empty() {
+			let cycles = if spawner.looping { (cycle_start / duration).floor() as i32 ..= (cycle_end / duration).floor() as i32 } else { 0..=0 };
+			// A tiny duration can cross thousands of cycles in one frame, so stop as soon as the per frame cap is reached
+			'cycles: for cycle in cycles {
+				for burst in &spawner.bursts {
+					let room = (MAX_SPAWNS_PER_FRAME as usize).saturating_sub(ages.len());
+					if room == 0 { break 'cycles; }
+					
+					let t = cycle as f32 * duration + burst.time;
+					if t >= cycle_start && t < cycle_end {
+						let count = ((burst.count as f32 * rate).round() as usize).min(room);
+						ages.extend(std::iter::repeat_n(cycle_end - t, count));
+					}
+				}
+			}
+		}
+		
+		spawner.cycle_time = if spawner.looping { cycle_end % duration } else { cycle_end };
+		
+		// Limits, with both backends alive counts the particles of both, but only the current one can be killed
+		let alive_entities = alive.get(&emitter_e).map_or(0, Vec::len);
+		let alive_instanced = spawner.buffers_entity.and_then(|e| buffers.get(e).ok()).map_or(0, |b| b.len());
+		let killable = match spawner.backend {
+			ParticleBackend::Entities => alive_entities,
+			ParticleBackend::Instanced => alive_instanced,
+		};
+		let alive_emitter = alive_entities + alive_instanced;
+		let (kill_emitter, count) = fit_budget(spawner.overflow, spawner.max_particles as usize, alive_emitter, killable, ages.len());
+		let (kill_global, count) = fit_budget(budget.overflow, budget.max_particles as usize, total_alive - kill_emitter, killable - kill_emitter, count);
+		let kill = kill_emitter + kill_global;
+		ages.truncate(count);
+		
+		if kill > 0 {
+			match spawner.backend {
+				ParticleBackend::Entities => if let Some(list) = alive.get_mut(&emitter_e) {
+					list.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+					for (e, _) in list.drain(..kill) {
+						sys.release(e, &mut commands);
+					}
 				},
-				Particle {
-					velocity: -transform.forward() * emit_speed + var * speed_variation,
+				ParticleBackend::Instanced => if let Some(mut b) = spawner.buffers_entity.and_then(|e| buffers.get_mut(e).ok()) {
+					b.kill_oldest(kill);
 				},
-			));
+			}
+		}
+		total_alive = total_alive - kill + ages.len();
+		
+		// Spawned into this and inserted on a new child if the emitter has none yet
+		let mut new_buffers = None;
+		let mut instanced = match spawner.backend {
+			ParticleBackend::Entities => None,
+			ParticleBackend::Instanced if ages.is_empty() => None,
+			ParticleBackend::Instanced => match spawner.buffers_entity.and_then(|e| buffers.get_mut(e).ok()) {
+				Some(b) => Some(b.into_inner()),
+				None => Some(new_buffers.insert(ParticleBuffers::default())),
+			},
+		};
+		
+		for age in ages.drain(..) {
+			let t = if dt > 0.0 { 1.0 - age / dt } else { 1.0 };
+			
+			let translation = prev.translation().lerp(transform.translation(), t);
+			let rotation = prev.rotation().slerp(transform.rotation(), t);
+			let scale = prev.scale().lerp(transform.scale(), t);
+			
+			let (local_pos, local_dir) = spawner.sample_shape(&mut sys.rng, &meshes);
+			let speed = random_range(&mut sys.rng, spawner.speed_min, spawner.speed_max);
+			let velocity = rotation * local_dir * speed + emitter_velocity * spawner.inherit_velocity;
+			let translation = translation + rotation * (local_pos * scale);
+			
+			let lifetime = random_range(&mut sys.rng, spawner.lifetime_min, spawner.lifetime_max);
+			let position = translation + velocity * age + 0.5 * GRAVITY * age * age;
+			
+			if let Some(buffers) = &mut instanced {
+				buffers.push(position, velocity + GRAVITY * age, age, lifetime);
+				continue;
+			}
+			
+			let life_t = age / lifetime.max(0.0001);
+			let palette_index = ParticleEmitter::palette_index(life_t);
+			let particle_scale = spawner.scale_over_life.sample(life_t).unwrap_or(1.0);
+			
+			let particle = Particle {
+				velocity: velocity + GRAVITY * age,
+				age,
+				lifetime,
+				emitter: emitter_e,
+				palette_index,
+			};
+			let transform = Transform {
+				translation: position,
+				rotation,
+				scale: Vec3::splat(particle_scale),
+			};
+			let material = spawner.palette[palette_index].clone();
+			
+			// Pooled entities keep all their components, so reusing them avoids spawns, only Disabled moves them between archetypes
+			if let Some(e) = sys.pool.pop() && let Ok((_, mut p, mut t, mut mat, _)) = particles.get_mut(e) {
+				*p = particle;
+				*t = transform;
+				mat.0 = material;
+				commands.entity(e).remove::<Disabled>();
+			}
+			else {
+				commands.spawn((
+					Mesh3d(sys.mesh.clone()),
+					MeshMaterial3d(material),
+					transform,
+					particle,
+				));
+			}
+		}
+		
+		if let Some(new_buffers) = new_buffers {
+			let e = commands.spawn((
+				Name::new("Particles"),
+				Mesh3d(sys.mesh.clone()),
+				ParticleInstances::default(),
+				new_buffers,
+				// Particles are in world space and go anywhere
+				NoFrustumCulling,
+				Pickable::IGNORE,
+				ChildOf(emitter_e),
+			)).id();
+			spawner.buffers_entity = Some(e);
 		}
 	}
 }
 fn update_particles(
 		time: Res<Time>,
-		particles: Query<(Entity, &mut Particle, &mut Transform)>,
+		emitters: Query<&ParticleEmitter>,
+		particles: Query<(Entity, &mut Particle, &mut Transform, &mut MeshMaterial3d<StandardMaterial>)>,
+		mut sys: ResMut<ParticleSystem>,
 		mut commands: Commands) {
-	let gravity: f32 = -10.0;
+	let dt = time.delta_secs();
 	
-	for (e, mut particle, mut transform) in particles {
-		particle.velocity += Vec3::new(0.0, gravity, 0.0) * time.delta_secs();
-		transform.translation += particle.velocity * time.delta_secs();
+	for (e, mut particle, mut transform, mut material) in particles {
+		particle.age += dt;
+		if particle.age >= particle.lifetime || transform.translation.y < 0.0 {
+			sys.release(e, &mut commands);
+			continue;
+		}
 		
-		if transform.translation.y < 0.0 {
-			commands.entity(e).despawn();
+		if let Ok(emitter) = emitters.get(particle.emitter) {
+			let t = particle.age / particle.lifetime;
+			
+			if let Some(drag) = emitter.drag_over_life.sample(t) && drag > 0.0 {
+				particle.velocity *= (-drag * dt).exp();
+			}
+			if let Some(scale) = emitter.scale_over_life.sample(t) {
+				transform.scale = Vec3::splat(scale);
+			}
+			
+			let index = ParticleEmitter::palette_index(t);
+			if index != particle.palette_index && let Some(mat) = emitter.palette.get(index) {
+				particle.palette_index = index;
+				material.0 = mat.clone();
+			}
+		}
+		
+		particle.velocity += GRAVITY * dt;
+		transform.translation += particle.velocity * dt;
+	}
+}
+
+fn simulate_instanced(
+		time: Res<Time>,
+		emitters: Query<&ParticleEmitter>,
+		buffers: Query<(&mut ParticleBuffers, &ChildOf)>) {
+	let dt = time.delta_secs();
+	
+	for (mut b, parent) in buffers {
+		let emitter = emitters.get(parent.parent()).ok();
+		let b = &mut *b;
+		
+		let mut i = 0;
+		while i < b.len() {
+			b.ages[i] += dt;
+			if b.ages[i] >= b.lifetimes[i] || b.positions[i].y < 0.0 {
+				b.swap_remove(i);
+				continue;
+			}
+			
+			if let Some(drag) = emitter.and_then(|e| e.drag_over_life.sample(b.ages[i] / b.lifetimes[i])) && drag > 0.0 {
+				b.velocities[i] *= (-drag * dt).exp();
+			}
+			b.velocities[i] += GRAVITY * dt;
+			b.positions[i] += b.velocities[i] * dt;
+			i += 1;
 		}
 	}
-}
\ No newline at end of file
+}
+
+// Instanced particles sample color exactly instead of using the palette
+fn write_instances(
+		emitters: Query<&ParticleEmitter>,
+		buffers: Query<(&ParticleBuffers, &mut ParticleInstances, &ChildOf)>) {
+	for (b, mut instances, parent) in buffers {
+		let Ok(emitter) = emitters.get(parent.parent()) else { continue };
+		
+		instances.0.clear();
+		instances.0.extend((0..b.len()).map(|i| {
+			let t = b.ages[i] / b.lifetimes[i];
+			ParticleInstance {
+				position: b.positions[i],
+				scale: emitter.scale_over_life.sample(t).unwrap_or(1.0),
+				color: emitter.color_over_life.sample(t).unwrap_or(LinearRgba::WHITE).to_f32_array(),
+			}
+		}));
+	}
+}
@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+use bevy::camera::primitives::Aabb;
+use bevy::window::PrimaryWindow;
+use bevy_egui::*;
+use bevy_egui::input::EguiWantsInput;
+use bevy_inspector_egui::bevy_inspector;
+use crate::phases::Phase;
+use crate::cursor::{ self, CursorManager };
+use crate::debug_render::DebugRenderCopy;
+use crate::particle_render::ParticleInstances;
+use crate::flycam::{ self, Flycam, ControlledFlycam };
+use crate::transform_gizmo::TransformGizmo;
+
+pub struct SelectionPlugin;
+impl Plugin for SelectionPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(Selection::default())
+			.add_systems(Update, (
+				click_select,
+				frame_selected.after(click_select),
+				draw_selection.after(click_select),
+			).in_set(Phase::Gameplay))
+			.add_systems(EguiPrimaryContextPass, selection_ui);
+	}
+}
+
+const SELECT_BTN : MouseButton = MouseButton::Left;
+// View Selected like in Blender
+const FRAME_SELECTED_KEYS : [KeyCode; 2] = [KeyCode::NumpadDecimal, KeyCode::Period];
+const SELECTION_COLOR : Color = Color::srgb(1.0, 0.8, 0.1);
+
+#[derive(Resource, Default, Reflect)]
+#[reflect(Resource)]
+pub struct Selection {
+	pub entity: Option<Entity>,
+}
+
+// Ray through the cursor from a camera rendering to the window
+pub fn cursor_ray(window: &Window, cam: &Camera, cam_transf: &GlobalTransform) -> Option<Ray3d> {
+	let cursor = window.cursor_position()?;
+	cam.viewport_to_world(cam_transf, cursor).ok()
+}
+
+// Ray from the currently controlled flycam, None while mouselooking or the cursor is outside the window
+pub fn controlled_camera_ray(
+		control: &ControlledFlycam,
+		cameras: &Query<(&Camera, &GlobalTransform), With<Flycam>>,
+		window: &Window) -> Option<(Entity, Ray3d)> {
+	let cam_e = control.current()?;
+	let (cam, cam_transf) = cameras.get(cam_e).ok()?;
+	Some((cam_e, cursor_ray(window, cam, cam_transf)?))
+}
+
+pub fn click_select(
+		mouse: Res<ButtonInput<MouseButton>>,
+		keyboard: Res<ButtonInput<KeyCode>>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		control: Res<ControlledFlycam>,
+		gizmo: Res<TransformGizmo>,
+		window: Single<&Window, With<PrimaryWindow>>,
+		cameras: Query<(&Camera, &GlobalTransform), With<Flycam>>,
+		copies: Query<&ChildOf, With<DebugRenderCopy>>,
+		particle_meshes: Query<(), With<ParticleInstances>>,
+		mut ray_cast: MeshRayCast,
+		mut selection: ResMut<Selection>) {
+	
+	if keyboard.just_pressed(KeyCode::Escape) && !cursor::keyboard_captured_by_ui(&cursor, &egui_wants) {
+		selection.entity = None;
+	}
+	
+	if !mouse.just_pressed(SELECT_BTN) || cursor.is_mouselook() || cursor::pointer_captured_by_ui(&cursor, &egui_wants)
+		|| gizmo.wants_pointer() {
+		return;
+	}
+	let Some((cam_e, ray)) = controlled_camera_ray(&control, &cameras, &window) else { return };
+	
+	// In debug render modes the debug camera only sees the copies, select their originals instead
+	let original = |e: Entity| copies.get(e).map_or(e, |parent| parent.parent());
+	
+	// Cameras have a debug cube mesh, don't pick the one we are looking out of
+	// and instanced particles only have a single mesh at the emitter
+	let filter = |e: Entity| original(e) != cam_e && !particle_meshes.contains(e);
+	let settings = MeshRayCastSettings::default()
+		.with_filter(&filter)
+		.with_visibility(RayCastVisibility::VisibleInView);
+	
+	// Clicking into empty space deselects
+	selection.entity = ray_cast.cast_ray(ray, &settings).first().map(|(e, _)| original(*e));
+}
+
+// Axis aligned world space (min, max) of a local Aabb
+pub fn aabb_to_world(aabb: &Aabb, transf: &GlobalTransform) -> (Vec3, Vec3) {
+	let mut min = Vec3::INFINITY;
+	let mut max = Vec3::NEG_INFINITY;
+	// Transform all 8 corners, since the entity might be rotated
+	for i in 0..8 {
+		let sign = Vec3::new(
+			if i & 1 != 0 { 1.0 } else { -1.0 },
+			if i & 2 != 0 { 1.0 } else { -1.0 },
+			if i & 4 != 0 { 1.0 } else { -1.0 });
+		let corner = transf.transform_point(Vec3::from(aabb.center) + Vec3::from(aabb.half_extents) * sign);
+		min = min.min(corner);
+		max = max.max(corner);
+	}
+	(min, max)
+}
+
+// World space bounds of an entity and all of its descendants with meshes
+pub fn world_bounds(
+		entity: Entity,
+		children: &Query<&Children>,
+		bounds: &Query<(&Aabb, &GlobalTransform)>) -> Option<(Vec3, Vec3)> {
+	let mut min = Vec3::INFINITY;
+	let mut max = Vec3::NEG_INFINITY;
+	
+	for e in std::iter::once(entity).chain(children.iter_descendants(entity)) {
+		if let Ok((aabb, transf)) = bounds.get(e) {
+			let (aabb_min, aabb_max) = aabb_to_world(aabb, transf);
+			min = min.min(aabb_min);
+			max = max.max(aabb_max);
+		}
+	}
+	
+	(min.x <= max.x).then_some((min, max))
+}
+
+fn frame_selected(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		control: Res<ControlledFlycam>,
+		selection: Res<Selection>,
+		children: Query<&Children>,
+		bounds: Query<(&Aabb, &GlobalTransform)>,
+		mut cameras: Query<(&Transform, &mut Flycam, &Projection, &Camera)>) {
+	
+	if !keyboard.any_just_pressed(FRAME_SELECTED_KEYS) || cursor::keyboard_captured_by_ui(&cursor, &egui_wants) {
+		return;
+	}
+	let Some(entity) = selection.entity else { return };
+	let Some(cam_e) = control.current() else { return };
+	let Ok((transf, mut flycam, proj, cam)) = cameras.get_mut(cam_e) else { return };
+	let Some((min, max)) = world_bounds(entity, &children, &bounds) else { return };
+	
+	let aspect = cam.logical_viewport_size().map_or(1.0, |size| size.x / size.y);
+	flycam::frame_bounds(transf, &mut flycam, proj, aspect, min, max);
+}
+
+fn draw_selection(
+		mut selection: ResMut<Selection>,
+		bounds: Query<(&Aabb, &GlobalTransform)>,
+		transforms: Query<&GlobalTransform>,
+		mut gizmos: Gizmos) {
+	let Some(entity) = selection.entity else { return };
+	
+	let Ok(transf) = transforms.get(entity) else {
+		// despawned
+		selection.entity = None;
+		return;
+	};
+	
+	// Oriented box around the mesh, or just the axes if the entity has no mesh
+	if let Ok((aabb, _)) = bounds.get(entity) {
+		let local = Transform::from_translation(aabb.center.into())
+			.with_scale(Vec3::from(aabb.half_extents) * 2.0);
+		gizmos.cuboid(transf.mul_transform(local), SELECTION_COLOR);
+	}
+	else {
+		gizmos.axes(*transf, 0.5);
+	}
+}
+
+fn selection_ui(world: &mut World) -> Result {
+	let Some(entity) = world.resource::<Selection>().entity else { return Ok(()) };
+	if world.get_entity(entity).is_err() {
+		return Ok(());
+	}
+	
+	let mut egui_context = world
+		.query_filtered::<&mut EguiContext, With<PrimaryEguiContext>>()
+		.single_mut(world)?.clone();
+	
+	let parent = world.get::<ChildOf>(entity).map(|c| c.parent());
+	let mut new_selection = None;
+	
+	egui::Window::new("Selected").show(egui_context.get_mut(), |ui| {
+		ui.horizontal(|ui| {
+			if let Some(parent) = parent {
+				if ui.button("Select Parent").clicked() {
+					new_selection = Some(Some(parent));
+				}
+			}
+			if ui.button("Deselect [Esc]").clicked() {
+				new_selection = Some(None);
+			}
+		});
+		
+		egui::ScrollArea::vertical().show(ui, |ui| {
+			bevy_inspector::ui_for_entity(world, entity, ui);
+		});
+	});
+	
+	if let Some(sel) = new_selection {
+		world.resource_mut::<Selection>().entity = sel;
+	}
+	
+	Ok(())
+}
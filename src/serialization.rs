@@ -52,6 +52,25 @@ macro_rules! serializer {
 	};
 }
 
+// Unit-only enums as their variant name, unknown names keep the current value
+macro_rules! serializer_enum {
+	($enum:ty, $($variant:ident),*) => {
+		impl crate::serialization::Serializer for $enum {
+			fn serialize(&self) -> serde_json::Value {
+				match self {
+					$(Self::$variant => stringify!($variant).into(),)*
+				}
+			}
+			fn deserialize(&mut self, json: serde_json::Value) {
+				match json.as_str() {
+					$(Some(stringify!($variant)) => *self = Self::$variant,)*
+					_ => {}
+				}
+			}
+		}
+	};
+}
+
 pub trait WorldSerializer {
 	// world mutable to allow getting queries (which are cached)
 	fn serialize(world: &mut World) -> serde_json::Value;
@@ -174,6 +193,7 @@ where D: Component<Mutability = bevy::ecs::component::Mutable> + Serializer, F:
 }
 
 pub(crate) use serializer;
+pub(crate) use serializer_enum;
 pub(crate) use serializer_world;
 pub(crate) use serialize_world;
 pub(crate) use deserialize_world;
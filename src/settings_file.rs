@@ -28,9 +28,24 @@ serializer_world!(SettingsFile{
 	window: crate::app_control::WindowSettings,
 	render: RenderSettings,
 	debug_cam: crate::debug_camera::DebugCameraState,
+	debug_cams: crate::debug_camera::DebugCameras,
+	overlays: crate::overlays::ReferenceOverlays,
 	main_cam: crate::flycam::Flycam,
+	scroll: crate::flycam::ScrollCalibration,
+	hud: crate::camera_hud::CameraHud,
+	particles: crate::particles::ParticleBudget,
 });
 
+// Current settings without touching the file, used by input recordings to reproduce the exact settings
+pub fn serialize_all(world: &mut World) -> serde_json::Value {
+	SettingsFile::serialize(world)
+}
+pub fn deserialize_all(world: &mut World, json: serde_json::Value) {
+	if !json.is_null() {
+		SettingsFile::deserialize(world, json);
+	}
+}
+
 pub fn save(world: &mut World) {
 	let json = SettingsFile::serialize(world);
 	
@@ -0,0 +1,133 @@
+use bevy::prelude::*;
+use bevy::mesh::skinning::SkinnedMesh;
+use bevy_egui::*;
+use bevy_egui::input::EguiWantsInput;
+use crate::phases::Phase;
+use crate::cursor::{ self, CursorManager };
+use crate::debug_render::DebugRenderCopy;
+use crate::flycam::Flycam;
+use crate::selection::{ self, Selection };
+
+pub struct SkeletonGizmosPlugin;
+impl Plugin for SkeletonGizmosPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.add_systems(Update, (
+				toggle_selected.in_set(Phase::Gameplay).after(selection::click_select),
+				draw_skeletons.after(Phase::CameraUpdate),
+			))
+			.add_systems(EguiPrimaryContextPass, draw_joint_labels);
+	}
+}
+
+const TOGGLE_SKELETON_KEY : KeyCode = KeyCode::KeyJ;
+const BONE_COLOR : Color = Color::srgb(1.0, 0.9, 0.2);
+const LABEL_COLOR : egui::Color32 = egui::Color32::from_rgb(255, 230, 50);
+
+// Draw the skeleton of a SkinnedMesh, add to the entity with the SkinnedMesh
+// (or press J with it or one of its ancestors selected)
+#[derive(Component, Reflect, Clone)]
+#[reflect(Component)]
+pub struct SkeletonGizmo {
+	pub axes: bool,
+	pub axes_size: f32,
+	// Joint index and name, to find the right joints[i]
+	pub labels: bool,
+}
+impl Default for SkeletonGizmo {
+	fn default() -> Self {
+		Self { axes: true, axes_size: 0.1, labels: true }
+	}
+}
+
+fn toggle_selected(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		selection: Res<Selection>,
+		children: Query<&Children>,
+		// Debug render copies share the skeleton of their original
+		skinned: Query<Has<SkeletonGizmo>, (With<SkinnedMesh>, Without<DebugRenderCopy>)>,
+		mut commands: Commands) {
+	if !keyboard.just_pressed(TOGGLE_SKELETON_KEY) || cursor::keyboard_captured_by_ui(&cursor, &egui_wants) {
+		return;
+	}
+	let Some(entity) = selection.entity else { return };
+	
+	// Selecting the scene root toggles all skinned meshes below it
+	let meshes: Vec<(Entity, bool)> = std::iter::once(entity).chain(children.iter_descendants(entity))
+		.filter_map(|e| Some((e, skinned.get(e).ok()?))).collect();
+	
+	// If any skeleton is shown hide all, otherwise show all
+	let show = !meshes.iter().any(|(_, shown)| *shown);
+	for (e, _) in meshes {
+		if show {
+			commands.entity(e).insert(SkeletonGizmo::default());
+		}
+		else {
+			commands.entity(e).remove::<SkeletonGizmo>();
+		}
+	}
+}
+
+fn draw_skeletons(
+		skeletons: Query<(&SkinnedMesh, &SkeletonGizmo)>,
+		joints: Query<(&GlobalTransform, Option<&ChildOf>)>,
+		mut gizmos: Gizmos) {
+	for (skinned, settings) in &skeletons {
+		for &joint in &skinned.joints {
+			let Ok((transf, parent)) = joints.get(joint) else { continue };
+			
+			// Bone from the parent joint, the root joint has none
+			if let Some(parent) = parent && skinned.joints.contains(&parent.parent()) {
+				if let Ok((parent_transf, _)) = joints.get(parent.parent()) {
+					gizmos.line(parent_transf.translation(), transf.translation(), BONE_COLOR);
+				}
+			}
+			
+			if settings.axes {
+				gizmos.axes(*transf, settings.axes_size);
+			}
+		}
+	}
+}
+
+fn draw_joint_labels(
+		mut contexts: EguiContexts,
+		skeletons: Query<(&SkinnedMesh, &SkeletonGizmo)>,
+		joints: Query<(&GlobalTransform, Option<&Name>)>,
+		cameras: Query<(&Camera, &GlobalTransform), With<Flycam>>) -> Result {
+	if !skeletons.iter().any(|(_, s)| s.labels) {
+		return Ok(());
+	}
+	
+	let ctx = contexts.ctx_mut()?;
+	// Behind egui windows
+	let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("joint_labels")));
+	
+	// Label in every active camera, clipped to its viewport (ex. picture-in-picture)
+	for (cam, cam_transf) in &cameras {
+		if !cam.is_active { continue; }
+		let Some(rect) = cam.logical_viewport_rect() else { continue };
+		let painter = painter.with_clip_rect(egui::Rect::from_min_max(
+			egui::pos2(rect.min.x, rect.min.y), egui::pos2(rect.max.x, rect.max.y)));
+		
+		for (skinned, settings) in &skeletons {
+			if !settings.labels { continue; }
+			
+			for (i, &joint) in skinned.joints.iter().enumerate() {
+				let Ok((transf, name)) = joints.get(joint) else { continue };
+				let Ok(pos) = cam.world_to_viewport(cam_transf, transf.translation()) else { continue };
+				
+				let text = match name {
+					Some(name) => format!("{i}: {name}"),
+					None => format!("{i}"),
+				};
+				painter.text(egui::pos2(pos.x + 4.0, pos.y), egui::Align2::LEFT_CENTER, text,
+					egui::FontId::proportional(12.0), LABEL_COLOR);
+			}
+		}
+	}
+	
+	Ok(())
+}
@@ -0,0 +1,474 @@
+use bevy::prelude::*;
+use bevy::window::{ PrimaryWindow, SystemCursorIcon };
+use bevy_egui::input::EguiWantsInput;
+use crate::phases::Phase;
+use crate::cursor::{ self, CursorManager, CursorRequest };
+use crate::flycam::{ Flycam, ControlledFlycam };
+use crate::selection::{ self, Selection };
+
+pub struct TransformGizmoPlugin;
+impl Plugin for TransformGizmoPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(TransformGizmo::default())
+			.insert_resource(UndoStack::default())
+			.add_systems(Update, (
+				gizmo_hotkeys,
+				undo_redo,
+				interact,
+				draw_gizmo,
+			).chain()
+			.in_set(Phase::Gameplay)
+			// Clicking a handle should not change the selection
+			.before(selection::click_select));
+	}
+}
+
+const DRAG_BTN : MouseButton = MouseButton::Left;
+
+const TRANSLATE_KEY : KeyCode = KeyCode::Digit1;
+const ROTATE_KEY : KeyCode = KeyCode::Digit2;
+const SCALE_KEY : KeyCode = KeyCode::Digit3;
+const TOGGLE_SPACE_KEY : KeyCode = KeyCode::KeyL;
+// Hold while dragging to snap to increments
+const SNAP_KEYS : [KeyCode; 2] = [KeyCode::ControlLeft, KeyCode::ControlRight];
+
+const AXIS_COLORS : [Color; 3] = [
+	Color::srgb(0.9, 0.2, 0.2),
+	Color::srgb(0.3, 0.9, 0.2),
+	Color::srgb(0.2, 0.4, 1.0),
+];
+const HIGHLIGHT_COLOR : Color = Color::srgb(1.0, 0.9, 0.2);
+const UNIFORM_COLOR : Color = Color::srgb(0.8, 0.8, 0.8);
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Reflect)]
+pub enum GizmoMode {
+	#[default]
+	Translate,
+	Rotate,
+	Scale,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Handle {
+	Axis(usize),
+	Plane(usize), // indexed by the plane normal
+	Ring(usize),
+	ScaleAxis(usize),
+	ScaleUniform,
+}
+
+#[derive(Clone, Copy)]
+struct Drag {
+	handle: Handle,
+	entity: Entity,
+	start_local: Transform,
+	start_world: Transform,
+	// Constraint geometry captured at drag start, so the gizmo does not move under the cursor
+	origin: Vec3,
+	axes: [Vec3; 3],
+	view_dir: Vec3,
+	start_param: f32, // along axis for Axis/ScaleAxis
+	start_point: Vec3, // on the plane for Plane/Ring/ScaleUniform
+}
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct TransformGizmo {
+	pub mode: GizmoMode,
+	// Scale always uses local axes
+	pub local_space: bool,
+	pub snap_translate: f32,
+	pub snap_rotate: f32, // radians
+	pub snap_scale: f32,
+	// Fraction of viewport height
+	pub size: f32,
+	
+	#[reflect(ignore)]
+	hovered: Option<Handle>,
+	#[reflect(ignore)]
+	drag: Option<Drag>,
+}
+impl Default for TransformGizmo {
+	fn default() -> Self {
+		Self {
+			mode: GizmoMode::Translate,
+			local_space: false,
+			snap_translate: 0.25,
+			snap_rotate: 15_f32.to_radians(),
+			snap_scale: 0.1,
+			size: 0.15,
+			hovered: None,
+			drag: None,
+		}
+	}
+}
+impl TransformGizmo {
+	// Gizmo is hovered or dragged, so clicks belong to it
+	pub fn wants_pointer(&self) -> bool {
+		self.hovered.is_some() || self.drag.is_some()
+	}
+}
+
+// Each finished drag is one undo step
+#[derive(Resource, Default)]
+struct UndoStack {
+	undo: Vec<(Entity, Transform, Transform)>, // (entity, before, after)
+	redo: Vec<(Entity, Transform, Transform)>,
+}
+
+fn gizmo_hotkeys(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		mut gizmo: ResMut<TransformGizmo>) {
+	if cursor::keyboard_captured_by_ui(&cursor, &egui_wants) || gizmo.drag.is_some() {
+		return;
+	}
+	
+	if keyboard.just_pressed(TRANSLATE_KEY) { gizmo.mode = GizmoMode::Translate; }
+	if keyboard.just_pressed(ROTATE_KEY) { gizmo.mode = GizmoMode::Rotate; }
+	if keyboard.just_pressed(SCALE_KEY) { gizmo.mode = GizmoMode::Scale; }
+	if keyboard.just_pressed(TOGGLE_SPACE_KEY) { gizmo.local_space = !gizmo.local_space; }
+}
+
+fn undo_redo(
+		keyboard: Res<ButtonInput<KeyCode>>,
+		cursor: Res<CursorManager>,
+		egui_wants: Res<EguiWantsInput>,
+		gizmo: Res<TransformGizmo>,
+		mut stack: ResMut<UndoStack>,
+		mut transforms: Query<&mut Transform>) {
+	if cursor::keyboard_captured_by_ui(&cursor, &egui_wants) || gizmo.drag.is_some() {
+		return;
+	}
+	
+	let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+	let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+	let undo = ctrl && !shift && keyboard.just_pressed(KeyCode::KeyZ);
+	let redo = ctrl && (keyboard.just_pressed(KeyCode::KeyY) || (shift && keyboard.just_pressed(KeyCode::KeyZ)));
+	
+	if undo {
+		// Skip entries of despawned entities
+		while let Some(step) = stack.undo.pop() {
+			if let Ok(mut transf) = transforms.get_mut(step.0) {
+				*transf = step.1;
+				stack.redo.push(step);
+				break;
+			}
+		}
+	}
+	else if redo {
+		while let Some(step) = stack.redo.pop() {
+			if let Ok(mut transf) = transforms.get_mut(step.0) {
+				*transf = step.2;
+				stack.undo.push(step);
+				break;
+			}
+		}
+	}
+}
+
+// World units per viewport height at the gizmo, to keep a constant size on screen
+fn screen_scale(origin: Vec3, cam_transf: &GlobalTransform, proj: &Projection) -> f32 {
+	match proj {
+		Projection::Perspective(persp) => {
+			let depth = (origin - cam_transf.translation()).dot(*cam_transf.forward()).max(0.001);
+			2.0 * depth * (persp.fov * 0.5).tan()
+		}
+		Projection::Orthographic(ortho) => ortho.area.height(),
+		_ => 1.0,
+	}
+}
+
+fn gizmo_axes(world: &Transform, gizmo: &TransformGizmo) -> [Vec3; 3] {
+	if gizmo.local_space || gizmo.mode == GizmoMode::Scale {
+		[world.rotation * Vec3::X, world.rotation * Vec3::Y, world.rotation * Vec3::Z]
+	} else {
+		[Vec3::X, Vec3::Y, Vec3::Z]
+	}
+}
+
+// Plane handles sit in this range along both of their axes
+const PLANE_MIN : f32 = 0.25;
+const PLANE_MAX : f32 = 0.45;
+const PICK_RADIUS : f32 = 0.06;
+
+// (param along line, param along ray, distance) of the closest points between a ray and a line
+fn closest_to_ray(ray: Ray3d, origin: Vec3, dir: Vec3) -> Option<(f32, f32, f32)> {
+	let r = *ray.direction;
+	let w = origin - ray.origin;
+	let b = dir.dot(r);
+	let denom = 1.0 - b*b;
+	if denom < 0.000001 {
+		return None; // parallel
+	}
+	let d = dir.dot(w);
+	let e = r.dot(w);
+	let s = (b*e - d) / denom;
+	let t = (e - b*d) / denom;
+	let dist = (origin + dir*s).distance(ray.origin + r*t);
+	Some((s, t, dist))
+}
+fn ray_plane(ray: Ray3d, origin: Vec3, normal: Vec3) -> Option<Vec3> {
+	let t = ray.intersect_plane(origin, InfinitePlane3d::new(normal))?;
+	Some(ray.get_point(t))
+}
+
+// Nearest handle under the ray
+fn pick_handle(ray: Ray3d, mode: GizmoMode, origin: Vec3, axes: &[Vec3; 3], scale: f32) -> Option<Handle> {
+	let mut best: Option<(Handle, f32)> = None;
+	let mut consider = |handle, t: f32| {
+		if t > 0.0 && best.is_none_or(|(_, best_t)| t < best_t) {
+			best = Some((handle, t));
+		}
+	};
+	
+	for i in 0..3 {
+		let axis = axes[i];
+		match mode {
+			GizmoMode::Translate | GizmoMode::Scale => {
+				if let Some((s, t, dist)) = closest_to_ray(ray, origin, axis) {
+					if s > 0.0 && s < scale && dist < PICK_RADIUS * scale {
+						consider(if mode == GizmoMode::Translate { Handle::Axis(i) } else { Handle::ScaleAxis(i) }, t);
+					}
+				}
+				
+				if mode == GizmoMode::Translate {
+					let (u, v) = (axes[(i+1) % 3], axes[(i+2) % 3]);
+					if let Some(p) = ray_plane(ray, origin, axis) {
+						let (pu, pv) = ((p - origin).dot(u) / scale, (p - origin).dot(v) / scale);
+						if (PLANE_MIN..PLANE_MAX).contains(&pu) && (PLANE_MIN..PLANE_MAX).contains(&pv) {
+							consider(Handle::Plane(i), (p - ray.origin).length());
+						}
+					}
+				}
+			}
+			GizmoMode::Rotate => {
+				if let Some(p) = ray_plane(ray, origin, axis) {
+					if ((p - origin).length() - scale).abs() < PICK_RADIUS * scale {
+						consider(Handle::Ring(i), (p - ray.origin).length());
+					}
+				}
+			}
+		}
+	}
+	
+	if mode == GizmoMode::Scale {
+		// Box at the center for uniform scale
+		let t = (origin - ray.origin).dot(*ray.direction);
+		if (ray.get_point(t) - origin).length() < PICK_RADIUS * 2.0 * scale {
+			consider(Handle::ScaleUniform, t);
+		}
+	}
+	
+	best.map(|(handle, _)| handle)
+}
+
+fn snap(value: f32, increment: f32, enabled: bool) -> f32 {
+	if enabled && increment > 0.0 { (value / increment).round() * increment } else { value }
+}
+
+// New world transform for the current ray, None if the constraint can't be evaluated this frame
+fn drag_world_transform(drag: &Drag, ray: Ray3d, gizmo: &TransformGizmo, snapping: bool) -> Option<Transform> {
+	let mut world = drag.start_world;
+	
+	match drag.handle {
+		Handle::Axis(i) => {
+			let (s, _, _) = closest_to_ray(ray, drag.origin, drag.axes[i])?;
+			let delta = snap(s - drag.start_param, gizmo.snap_translate, snapping);
+			world.translation += drag.axes[i] * delta;
+		}
+		Handle::Plane(i) => {
+			let p = ray_plane(ray, drag.origin, drag.axes[i])?;
+			let delta = p - drag.start_point;
+			for axis in [drag.axes[(i+1) % 3], drag.axes[(i+2) % 3]] {
+				world.translation += axis * snap(delta.dot(axis), gizmo.snap_translate, snapping);
+			}
+		}
+		Handle::Ring(i) => {
+			let axis = drag.axes[i];
+			let p = ray_plane(ray, drag.origin, axis)?;
+			let (from, to) = (drag.start_point - drag.origin, p - drag.origin);
+			let angle = from.cross(to).dot(axis).atan2(from.dot(to));
+			let angle = snap(angle, gizmo.snap_rotate, snapping);
+			world.rotation = (Quat::from_axis_angle(axis, angle) * drag.start_world.rotation).normalize();
+		}
+		Handle::ScaleAxis(i) => {
+			let (s, _, _) = closest_to_ray(ray, drag.origin, drag.axes[i])?;
+			let factor = snap(s / drag.start_param.max(0.0001), gizmo.snap_scale, snapping);
+			world.scale[i] = drag.start_world.scale[i] * factor;
+		}
+		Handle::ScaleUniform => {
+			let p = ray_plane(ray, drag.origin, drag.view_dir)?;
+			let factor = (p - drag.origin).length() / (drag.start_point - drag.origin).length().max(0.0001);
+			let factor = snap(factor, gizmo.snap_scale, snapping);
+			world.scale = drag.start_world.scale * factor;
+		}
+	}
+	Some(world)
+}
+
+fn interact(
+		mouse: Res<ButtonInput<MouseButton>>,
+		keyboard: Res<ButtonInput<KeyCode>>,
+		egui_wants: Res<EguiWantsInput>,
+		control: Res<ControlledFlycam>,
+		selection: Res<Selection>,
+		window: Single<&Window, With<PrimaryWindow>>,
+		cameras: Query<(&Camera, &GlobalTransform), With<Flycam>>,
+		projections: Query<&Projection>,
+		globals: Query<&GlobalTransform>,
+		parents: Query<&ChildOf>,
+		mut transforms: Query<&mut Transform>,
+		mut cursor: ResMut<CursorManager>,
+		mut stack: ResMut<UndoStack>,
+		mut gizmo: ResMut<TransformGizmo>) {
+	
+	gizmo.hovered = None;
+	
+	let ray = selection::controlled_camera_ray(&control, &cameras, &window);
+	
+	if let Some(drag) = gizmo.drag {
+		// Finished, or selection changed under us
+		if !mouse.pressed(DRAG_BTN) || selection.entity != Some(drag.entity) {
+			if let Ok(transf) = transforms.get(drag.entity) {
+				if *transf != drag.start_local {
+					stack.undo.push((drag.entity, drag.start_local, *transf));
+					stack.redo.clear();
+				}
+			}
+			gizmo.drag = None;
+			return;
+		}
+		
+		cursor.request(CursorRequest {
+			priority: cursor::PRIORITY_DRAG,
+			icon: Some(SystemCursorIcon::Grabbing.into()),
+			..default()
+		});
+		
+		let Some((_, ray)) = ray else { return };
+		let snapping = keyboard.any_pressed(SNAP_KEYS);
+		let Some(world) = drag_world_transform(&drag, ray, &gizmo, snapping) else { return };
+		
+		// Back into parent space, ex. for skeleton joints
+		let local = match parents.get(drag.entity).ok().and_then(|c| globals.get(c.parent()).ok()) {
+			Some(parent) => Transform::from_matrix(parent.affine().inverse() * world.to_matrix()),
+			None => world,
+		};
+		if let Ok(mut transf) = transforms.get_mut(drag.entity) {
+			*transf = local;
+		}
+		return;
+	}
+	
+	if cursor.is_mouselook() || egui_wants.wants_any_pointer_input() {
+		return;
+	}
+	let Some(entity) = selection.entity else { return };
+	let Some((cam_e, ray)) = ray else { return };
+	let (Ok((_, cam_transf)), Ok(proj), Ok(global)) = (cameras.get(cam_e), projections.get(cam_e), globals.get(entity)) else { return };
+	
+	let world = global.compute_transform();
+	let axes = gizmo_axes(&world, &gizmo);
+	let scale = gizmo.size * screen_scale(world.translation, cam_transf, proj);
+	
+	gizmo.hovered = pick_handle(ray, gizmo.mode, world.translation, &axes, scale);
+	let Some(handle) = gizmo.hovered else { return };
+	
+	cursor.request(CursorRequest {
+		priority: cursor::PRIORITY_HOVER,
+		icon: Some(SystemCursorIcon::Grab.into()),
+		..default()
+	});
+	
+	if mouse.just_pressed(DRAG_BTN) {
+		let Ok(start_local) = transforms.get(entity) else { return };
+		let origin = world.translation;
+		let view_dir = *cam_transf.forward();
+		
+		let (start_param, start_point) = match handle {
+			Handle::Axis(i) | Handle::ScaleAxis(i) => {
+				let Some((s, _, _)) = closest_to_ray(ray, origin, axes[i]) else { return };
+				(s, origin)
+			}
+			Handle::Plane(i) | Handle::Ring(i) => {
+				let Some(p) = ray_plane(ray, origin, axes[i]) else { return };
+				(0.0, p)
+			}
+			Handle::ScaleUniform => {
+				let Some(p) = ray_plane(ray, origin, view_dir) else { return };
+				(0.0, p)
+			}
+		};
+		
+		gizmo.drag = Some(Drag {
+			handle, entity,
+			start_local: *start_local,
+			start_world: world,
+			origin, axes, view_dir,
+			start_param, start_point,
+		});
+	}
+}
+
+fn draw_gizmo(
+		gizmo: Res<TransformGizmo>,
+		selection: Res<Selection>,
+		control: Res<ControlledFlycam>,
+		cameras: Query<(&GlobalTransform, &Projection), With<Flycam>>,
+		globals: Query<&GlobalTransform>,
+		mut gizmos: Gizmos) {
+	let Some(entity) = selection.entity else { return };
+	let Some((cam_transf, proj)) = control.current().and_then(|e| cameras.get(e).ok()) else { return };
+	let Ok(global) = globals.get(entity) else { return };
+	
+	let world = global.compute_transform();
+	let active = gizmo.drag.map(|d| d.handle).or(gizmo.hovered);
+	let color = |handle: Handle, base: Color| if active == Some(handle) { HIGHLIGHT_COLOR } else { base };
+	
+	// While dragging keep drawing the drag start axes, so the handle does not spin with the rotation
+	let (origin, axes) = match gizmo.drag {
+		Some(drag) => (world.translation, drag.axes),
+		None => (world.translation, gizmo_axes(&world, &gizmo)),
+	};
+	let scale = gizmo.size * screen_scale(origin, cam_transf, proj);
+	
+	for i in 0..3 {
+		let axis = axes[i];
+		match gizmo.mode {
+			GizmoMode::Translate => {
+				gizmos.arrow(origin, origin + axis * scale, color(Handle::Axis(i), AXIS_COLORS[i]));
+				
+				let (u, v) = (axes[(i+1) % 3] * scale, axes[(i+2) % 3] * scale);
+				let corners = [
+					origin + u*PLANE_MIN + v*PLANE_MIN,
+					origin + u*PLANE_MAX + v*PLANE_MIN,
+					origin + u*PLANE_MAX + v*PLANE_MAX,
+					origin + u*PLANE_MIN + v*PLANE_MAX,
+				];
+				gizmos.linestrip([corners[0], corners[1], corners[2], corners[3], corners[0]],
+					color(Handle::Plane(i), AXIS_COLORS[i]));
+			}
+			GizmoMode::Rotate => {
+				let iso = Isometry3d::new(origin, Quat::from_rotation_arc(Vec3::Z, axis));
+				gizmos.circle(iso, scale, color(Handle::Ring(i), AXIS_COLORS[i])).resolution(64);
+			}
+			GizmoMode::Scale => {
+				let end = origin + axis * scale;
+				let c = color(Handle::ScaleAxis(i), AXIS_COLORS[i]);
+				gizmos.line(origin, end, c);
+				gizmos.cuboid(Transform::from_translation(end)
+					.with_rotation(world.rotation)
+					.with_scale(Vec3::splat(PICK_RADIUS * 1.5 * scale)), c);
+			}
+		}
+	}
+	
+	if gizmo.mode == GizmoMode::Scale {
+		gizmos.cuboid(Transform::from_translation(origin)
+			.with_rotation(world.rotation)
+			.with_scale(Vec3::splat(PICK_RADIUS * 2.0 * scale)), color(Handle::ScaleUniform, UNIFORM_COLOR));
+	}
+}